@@ -0,0 +1,190 @@
+// Counts the heap allocations per batch of the path before buffers were reused
+// (`legacy_preprocess` below, as `preprocess` was then, `OrtBackend::run` and
+// `postprocess`) against `YOLOv8::run`, which reuses its input and output buffers,
+// on synthetic frames. The legacy path still gets the buffered input conversion of
+// non-f32 models, which used to be a new array per batch. Needs a model; the args
+// toml defaults to `model_args.toml`. Only allocations on the Rust side are counted,
+// not those inside ONNX Runtime: ort 1.16 has no I/O binding to host memory, so the
+// session allocates every output and it is copied out.
+//
+// cargo bench --bench allocations -- ./model_args.toml
+
+use app::multi_capture::pad_to_size;
+use app::{Args, YOLOTask, YOLOv8};
+use image::{DynamicImage, GenericImageView, RgbImage};
+use ndarray::{Array, Axis, IxDyn};
+use rand::{thread_rng, Rng};
+use rayon::prelude::*;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const ITERATIONS: usize = 20;
+const WIDTH: u32 = 1280;
+const HEIGHT: u32 = 720;
+
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        BYTES.fetch_add(new_size, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+fn measure(mut f: impl FnMut()) -> (usize, usize, Duration) {
+    // warm up once, then the mean over the iterations
+    f();
+    let (allocations, bytes) = (
+        ALLOCATIONS.load(Ordering::Relaxed),
+        BYTES.load(Ordering::Relaxed),
+    );
+    let t = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    let elapsed = t.elapsed();
+    (
+        (ALLOCATIONS.load(Ordering::Relaxed) - allocations) / ITERATIONS,
+        (BYTES.load(Ordering::Relaxed) - bytes) / ITERATIONS,
+        elapsed / ITERATIONS as u32,
+    )
+}
+
+fn legacy_preprocess(model: &YOLOv8, xs: &[DynamicImage]) -> Array<f32, IxDyn> {
+    // a resized and a padded copy of every image and a Vec of its pixels,
+    // assigned into a new batch array
+    let (height, width) = (model.height(), model.width());
+    let mut ys = Array::from_elem(
+        (xs.len(), 3, height as usize, width as usize),
+        144.0 / 255.0,
+    )
+    .into_dyn();
+    ys.axis_iter_mut(Axis(0))
+        .into_par_iter()
+        .enumerate()
+        .for_each(|(idx, mut ys_slice)| {
+            let x = &xs[idx];
+            let img = match model.task() {
+                YOLOTask::Classify => {
+                    x.resize_exact(width, height, image::imageops::FilterType::Triangle)
+                }
+                _ => {
+                    let (w0, h0) = x.dimensions();
+                    let (_, w_new, h_new) =
+                        model.scale_wh(w0 as f32, h0 as f32, width as f32, height as f32);
+                    if !(w_new == width as f32 && h_new == height as f32) {
+                        x.resize_exact(
+                            w_new as u32,
+                            h_new as u32,
+                            image::imageops::FilterType::Triangle,
+                        )
+                    } else {
+                        x.clone()
+                    }
+                }
+            };
+            let img = pad_to_size(img, height, width, 144);
+            let res = img
+                .as_rgb8()
+                .expect("valid RGB8")
+                .par_iter()
+                .map(|&b| (b as f32) / 255.0)
+                .collect::<Vec<_>>();
+            let res = Array::from_shape_vec((height as usize, width as usize, 3), res)
+                .expect("valid matrix")
+                .permuted_axes([2, 0, 1]);
+            ys_slice.assign(&res);
+        });
+    ys
+}
+
+fn main() {
+    let toml = std::env::args()
+        .skip(1)
+        .find(|arg| arg.ends_with(".toml"))
+        .unwrap_or("./model_args.toml".to_string());
+    let mut args = match Args::new_from_toml(Path::new(&toml)) {
+        Ok(args) => args,
+        Err(e) => {
+            println!("Skipping, no model: {e}");
+            return;
+        }
+    };
+    args.warmup = 0;
+    args.self_test = false;
+    args.plot = false;
+    args.profile = false;
+    let mut model = match YOLOv8::new(args) {
+        Ok(model) => model,
+        Err(e) => {
+            println!("Skipping, no model: {e}");
+            return;
+        }
+    };
+
+    let mut rng = thread_rng();
+    let xs: Vec<_> = (0..model.batch())
+        .map(|_| {
+            DynamicImage::from(RgbImage::from_fn(WIDTH, HEIGHT, |_, _| {
+                image::Rgb([rng.gen(), rng.gen(), rng.gen()])
+            }))
+        })
+        .collect();
+
+    let allocating = measure(|| {
+        let x = legacy_preprocess(&model, &xs);
+        let ys: Vec<_> = model
+            .engine()
+            .run(x.view(), false)
+            .unwrap()
+            .into_iter()
+            .map(Arc::new)
+            .collect();
+        model.postprocess(&ys, &xs).unwrap();
+    });
+    let buffered = measure(|| {
+        model.run(&xs, false).unwrap();
+    });
+
+    println!(
+        "Allocations per batch of {} {}x{} frames ({} iterations)",
+        xs.len(),
+        WIDTH,
+        HEIGHT,
+        ITERATIONS
+    );
+    for (name, (allocations, bytes, elapsed)) in
+        [("allocating", allocating), ("buffered", buffered)]
+    {
+        println!(
+            "> {name:<10}: {allocations} allocations, {:.1} MiB, {elapsed:?}",
+            bytes as f64 / (1024. * 1024.)
+        );
+    }
+}
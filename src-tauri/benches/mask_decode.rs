@@ -0,0 +1,116 @@
+// Compares the old per-detection mask decoding of `YOLOv8::postprocess` (one
+// matmul, a full-resolution resize and a per-pixel crop loop per detection)
+// against the lazy, batched `Masks::decode`, on synthetic YOLOv8-seg outputs.
+//
+// cargo bench --bench mask_decode
+
+use app::{Bbox, Masks};
+use image::{DynamicImage, ImageBuffer};
+use ndarray::{s, Array, Array2, IxDyn};
+use rand::{thread_rng, Rng};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const ITERATIONS: u32 = 20;
+const NUM_DETECTIONS: usize = 16;
+const NM: usize = 32;
+const PROTO_SIZE: usize = 160;
+const WIDTH: u32 = 640;
+const HEIGHT: u32 = 480;
+
+fn legacy(protos: &Array<f32, IxDyn>, coefs: &Array2<f32>, bboxes: &[Bbox]) -> Vec<Vec<u8>> {
+    let mut masks = Vec::new();
+    for (coef, bbox) in coefs.outer_iter().zip(bboxes.iter()) {
+        let proto = protos.slice(s![0, .., .., ..]);
+        let (nm, nh, nw) = proto.dim();
+        let coef = coef.to_owned().into_shape((1, nm)).unwrap();
+        let proto = proto.to_owned().into_shape((nm, nh * nw)).unwrap();
+        let mask = coef.dot(&proto).into_shape((nh, nw, 1)).unwrap();
+        let mask_im: ImageBuffer<image::Luma<_>, Vec<f32>> =
+            ImageBuffer::from_raw(nw as u32, nh as u32, mask.into_raw_vec()).unwrap();
+        let mut mask_im = DynamicImage::from(mask_im);
+        let r = (nw as f32 / WIDTH as f32).min(nh as f32 / HEIGHT as f32);
+        let mask_cropped = mask_im.crop(
+            0,
+            0,
+            (WIDTH as f32 * r).round() as u32,
+            (HEIGHT as f32 * r).round() as u32,
+        );
+        let mut mask_original = mask_cropped
+            .resize_exact(WIDTH, HEIGHT, image::imageops::FilterType::CatmullRom)
+            .into_luma8();
+        let (w, h) = mask_original.dimensions();
+        for y in 0..h as usize {
+            for x in 0..w as usize {
+                if x < bbox.xmin() as usize
+                    || x > bbox.xmax() as usize
+                    || y < bbox.ymin() as usize
+                    || y > bbox.ymax() as usize
+                {
+                    mask_original.put_pixel(x as u32, y as u32, image::Luma([0u8]));
+                }
+            }
+        }
+        masks.push(mask_original.into_raw());
+    }
+    masks
+}
+
+fn main() {
+    let mut rng = thread_rng();
+    let protos = Arc::new(
+        Array::from_shape_fn((1, NM, PROTO_SIZE, PROTO_SIZE), |_| {
+            rng.gen_range(-1.0..1.0)
+        })
+        .into_dyn(),
+    );
+    let coefs = Array2::from_shape_fn((NUM_DETECTIONS, NM), |_| rng.gen_range(-1.0..1.0));
+    let bboxes: Vec<_> = (0..NUM_DETECTIONS)
+        .map(|i| {
+            let (w, h) = (rng.gen_range(20.0..200.0), rng.gen_range(20.0..200.0));
+            let x = rng.gen_range(0.0..WIDTH as f32 - w);
+            let y = rng.gen_range(0.0..HEIGHT as f32 - h);
+            Bbox::new(x, y, w, h, i % 5, 0.9)
+        })
+        .collect();
+
+    let mut t_legacy = Duration::ZERO;
+    let mut t_unused = Duration::ZERO;
+    let mut t_decoded = Duration::ZERO;
+    for _ in 0..ITERATIONS {
+        let t = Instant::now();
+        let masks = legacy(&protos, &coefs, &bboxes);
+        t_legacy += t.elapsed();
+        assert_eq!(masks.len(), NUM_DETECTIONS);
+
+        // what postprocess pays when nobody reads the masks ...
+        let t = Instant::now();
+        let masks = Masks::new(
+            Arc::clone(&protos),
+            0,
+            coefs.clone(),
+            bboxes.clone(),
+            WIDTH,
+            HEIGHT,
+        );
+        t_unused += t.elapsed();
+
+        // ... and when they are plotted or exported
+        assert_eq!(masks.decode().len(), NUM_DETECTIONS);
+        t_decoded += t.elapsed();
+    }
+
+    let legacy = t_legacy / ITERATIONS;
+    let decoded = t_decoded / ITERATIONS;
+    println!(
+        "Mask decoding, {} detections, {}x{} image, {}x{} protos ({} iterations)",
+        NUM_DETECTIONS, WIDTH, HEIGHT, PROTO_SIZE, PROTO_SIZE, ITERATIONS
+    );
+    println!("> per-detection (old): {:?}", legacy);
+    println!(
+        "> batched, decoded:    {:?} ({:.1}x)",
+        decoded,
+        legacy.as_secs_f64() / decoded.as_secs_f64()
+    );
+    println!("> batched, unused:     {:?}", t_unused / ITERATIONS);
+}
@@ -1,41 +1,168 @@
 use crate::args::Args;
-use crate::model::YOLOv8;
+use crate::ensemble::Ensemble;
+use crate::export::{ExportFormat, Exporter};
+use crate::model::{Thresholds, YOLOv8};
 use crate::multi_capture::{self, setup_captures};
+use crate::record::{MaskEncoding, ResultRecord};
+use crate::registry::{Manifest, ModelRecord};
+use crate::self_test::SelfTestReport;
+use crate::yolo_result::YOLOResult;
+use crate::LoadError;
 use image::{DynamicImage, ImageFormat};
-use log::info;
-use std::path::Path;
-use std::sync::mpsc;
+use log::{error, info, warn};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
+use tauri::Manager;
 
 const NUM_CAMERAS: usize = 3;
 const POLL_DURATION: Duration = Duration::from_secs(30);
 const INFERENCE: bool = true;
 const IMAGE_FORMAT: ImageFormat = ImageFormat::Bmp;
-const LOG_INTERVAL: u32 = 5; 
+const LOG_INTERVAL: u32 = 5;
+const MODEL_ARGS: &str = "./model_args.toml";
+const MODELS_DIR: &str = "./models";
+// batches of frames the export writer can fall behind by before frames are skipped
+const EXPORT_QUEUE: usize = 30;
+
+// Model handles shared between the tauri commands and the inference thread.
+#[derive(Default)]
+pub struct ModelState {
+    // hands updates to the inference thread, which applies them between batches
+    update_tx: Mutex<Option<mpsc::Sender<ModelUpdate>>>,
+    // a model that has been loaded but not activated yet
+    staged: Mutex<Option<(ActiveModel, YOLOv8)>>,
+    // the model the inference thread is running
+    active: Mutex<Option<ActiveModel>>,
+    // frames go out unplotted, the frontend draws the detections
+    client_overlay: AtomicBool,
+    // writer thread of the running export, if any
+    export: Mutex<Option<ExportWriter>>,
+}
+
+// A frame to export and the results of the primary model on it.
+type ExportFrame = (String, DynamicImage, YOLOResult);
+
+// Owns the `Exporter` of a running export and writes the frames it is sent.
+pub struct ExportWriter {
+    dir: String,
+    tx: mpsc::SyncSender<ExportFrame>,
+    handle: thread::JoinHandle<Result<(usize, PathBuf), String>>,
+    skipped: usize,
+}
+
+// Changes to the model of the inference thread.
+pub enum ModelUpdate {
+    Swap(ActiveModel, Box<YOLOv8>),
+    Thresholds(Thresholds, bool), // and whether to persist them
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ActiveModel {
+    model: String,
+    // the model's own args toml, where its thresholds are persisted. None for a registered
+    // model without one, which runs on the shared default args toml.
+    args: Option<String>,
+    names: Vec<String>,
+    thresholds: Thresholds,
+    self_test: Option<SelfTestReport>,
+}
+
+impl ActiveModel {
+    fn new(args: Option<String>, yolo: &YOLOv8) -> Self {
+        Self {
+            model: yolo.model_id().to_string(),
+            args,
+            names: yolo.names().clone(),
+            thresholds: yolo.thresholds(),
+            self_test: yolo.self_test().cloned(),
+        }
+    }
+}
+
+// A frame on its way to an emitter thread.
+pub struct Frame {
+    img: DynamicImage,
+    detections: Option<DetectionsPayload>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct DetectionsPayload {
+    // Results of one frame, one record per model in drawing order, and the size of
+    // the frame they refer to. `plotted` when they are already drawn on the frame.
+    seq: u64,
+    width: u32,
+    height: u32,
+    plotted: bool,
+    results: Vec<ResultRecord>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ModelEntry {
+    record: ModelRecord,
+    active: bool,
+}
+
+// The models directory of the default args toml.
+fn models_dir() -> String {
+    Args::new_from_toml(Path::new(MODEL_ARGS))
+        .map_or(MODELS_DIR.to_string(), |args| args.models_dir)
+}
+
+// The self-test images of a bundled app, which are resources and not in the working directory.
+fn resolve_self_test_images(app: &tauri::AppHandle, args: &mut Args) {
+    if Path::new(&args.self_test_images).is_dir() {
+        return;
+    }
+    if let Some(dir) = app
+        .path_resolver()
+        .resolve_resource(&args.self_test_images)
+        .filter(|dir| dir.is_dir())
+    {
+        args.self_test_images = dir.to_string_lossy().to_string();
+    }
+}
+
+// Sends an update to the inference thread.
+fn send_update(state: &ModelState, update: ModelUpdate) -> Result<(), String> {
+    match state.update_tx.lock().unwrap().as_ref() {
+        Some(tx) => tx
+            .send(update)
+            .map_err(|_| "Inference thread is not running.".to_string()),
+        None => Err("Streaming has not started yet.".to_string()),
+    }
+}
 
 // Sets up the emitter thread for a view.
-fn setup_emitter(rx: mpsc::Receiver<DynamicImage>, window: tauri::Window, win_index: usize) {
+fn setup_emitter(rx: mpsc::Receiver<Frame>, window: tauri::Window, win_index: usize) {
     // ~60ms per emission excluding waiting for the next frame
     // would only be bottleneck if we are running > 20fps
     loop {
-        let img = rx
+        let frame = rx
             .recv()
             .expect("Failed to recieve batch from capture thread.");
+        // the detections go first, so they are there when their frame arrives
+        if let Some(detections) = frame.detections {
+            window
+                .emit(&format!("detections-{}", win_index)[..], detections)
+                .expect("Failed to emit detections.");
+        }
         window
             .emit(
                 &format!("image-payload-{}", win_index)[..],
-                multi_capture::convert_to_bytes(&img, IMAGE_FORMAT),
+                multi_capture::convert_to_bytes(&frame.img, IMAGE_FORMAT),
             )
             .expect("Failed to emit image payload.");
     }
 }
 
 // Sets up the emitter threads for each view.
-fn setup_emitters(window: tauri::Window, num_cameras: i32) -> Vec<mpsc::SyncSender<DynamicImage>> {
+fn setup_emitters(window: tauri::Window, num_cameras: i32) -> Vec<mpsc::SyncSender<Frame>> {
     let mut senders = vec![];
     for i in 0..num_cameras {
-        let (tx, rx) = mpsc::sync_channel::<DynamicImage>(5);
+        let (tx, rx) = mpsc::sync_channel::<Frame>(5);
         let w_clone = window.clone();
         thread::Builder::new()
             .name(format!("emitter thread {i}"))
@@ -60,6 +187,260 @@ pub fn poll_and_emit_image_sources(window: tauri::Window) {
         .expect("Failed to spawn poll image sources thread.");
 }
 
+// Lists the models registered in the manifest of the models directory, with their metadata.
+#[tauri::command]
+pub fn list_models(state: tauri::State<ModelState>) -> Result<Vec<ModelEntry>, String> {
+    let active = state.active.lock().unwrap().clone();
+    let manifest = Manifest::load(Path::new(&models_dir())).map_err(|e| e.to_string())?;
+    Ok(manifest
+        .models()
+        .iter()
+        .map(|record| ModelEntry {
+            active: active.as_ref().is_some_and(|a| &a.model == record.id()),
+            record: record.clone(),
+        })
+        .collect())
+}
+
+/*
+Builds a registered model in the background so streaming is not interrupted.
+Its file is checked against the SHA-256 of the manifest first. Once it is
+ready it is either sent to the inference thread right away (`activate`) or
+kept as the staged model for `activate_model`. Any failure is emitted as
+`model-error` and the running model stays in place.
+*/
+#[tauri::command]
+pub fn load_model(window: tauri::Window, model: String, activate: bool) {
+    thread::Builder::new()
+        .name("model loader thread".to_string())
+        .spawn(move || {
+            info!("Loading model {model}...");
+            let app = window.app_handle();
+            let state = app.state::<ModelState>();
+
+            // the model's own args toml from the manifest, or the default one
+            let dir = models_dir();
+            let result = Manifest::load(Path::new(&dir))
+                .and_then(|manifest| {
+                    let args = manifest
+                        .get(&model)?
+                        .args_path(Path::new(&dir))
+                        .map(|path| path.to_string_lossy().to_string());
+                    let mut config =
+                        Args::new_from_toml(Path::new(args.as_deref().unwrap_or(MODEL_ARGS)))?;
+                    config.model_id = Some(model.clone());
+                    config.models_dir = dir.clone();
+                    resolve_self_test_images(&app, &mut config);
+                    Ok((args, YOLOv8::new(config)?))
+                })
+                .map_err(|e| e.to_string())
+                .and_then(|(args, yolo)| {
+                    let active = ActiveModel::new(args, &yolo);
+                    if let Some(report) = yolo.self_test() {
+                        window
+                            .emit("self-test", report)
+                            .expect("Failed to emit self-test.");
+                    }
+                    if activate {
+                        send_update(&state, ModelUpdate::Swap(active, Box::new(yolo)))
+                    } else {
+                        *state.staged.lock().unwrap() = Some((active, yolo));
+                        Ok(())
+                    }
+                });
+
+            match result {
+                Ok(()) => {
+                    info!("Model {model} loaded.");
+                    window
+                        .emit("model-loaded", &model)
+                        .expect("Failed to emit model-loaded.");
+                }
+                Err(e) => {
+                    error!("Failed to load model {model}: {e}");
+                    window
+                        .emit("model-error", format!("{model}: {e}"))
+                        .expect("Failed to emit model-error.");
+                }
+            }
+        })
+        .expect("Failed to spawn model loader thread.");
+}
+
+// Swaps the staged model into the inference thread.
+#[tauri::command]
+pub fn activate_model(state: tauri::State<ModelState>) -> Result<(), String> {
+    let staged = state.staged.lock().unwrap().take();
+    match staged {
+        Some((active, yolo)) => send_update(&state, ModelUpdate::Swap(active, Box::new(yolo))),
+        None => Err("No model has been loaded.".to_string()),
+    }
+}
+
+// Returns the warm-up and self-test report of the running model.
+#[tauri::command]
+pub fn get_self_test(state: tauri::State<ModelState>) -> Result<Option<SelfTestReport>, String> {
+    match state.active.lock().unwrap().as_ref() {
+        Some(active) => Ok(active.self_test.clone()),
+        None => Err("No model is running.".to_string()),
+    }
+}
+
+// Returns whether the frontend draws the detections itself.
+#[tauri::command]
+pub fn get_client_overlay(state: tauri::State<ModelState>) -> bool {
+    state.client_overlay.load(Ordering::Relaxed)
+}
+
+// Switches between frames plotted here and plain frames the frontend draws the detections on.
+#[tauri::command]
+pub fn set_client_overlay(state: tauri::State<ModelState>, enabled: bool) {
+    state.client_overlay.store(enabled, Ordering::Relaxed);
+}
+
+/*
+Starts exporting every frame of the running session with the results of the
+primary model, in `format` under `dir`, until `stop_export`. Categories are
+the classes of the model running when the export started.
+*/
+#[tauri::command]
+pub fn start_export(
+    state: tauri::State<ModelState>,
+    dir: String,
+    format: ExportFormat,
+    masks: MaskEncoding,
+) -> Result<(), String> {
+    let names = match state.active.lock().unwrap().as_ref() {
+        Some(active) => active.names.clone(),
+        None => return Err("No model is running.".to_string()),
+    };
+    let mut export = state.export.lock().unwrap();
+    if let Some(writer) = export.as_ref() {
+        return Err(format!("Already exporting to {}.", writer.dir));
+    }
+    let mut exporter =
+        Exporter::new(Path::new(&dir), format, names, masks).map_err(|e| e.to_string())?;
+
+    // encoding and writing the frames is left to a thread of its own, off the inference loop
+    let (tx, rx) = mpsc::sync_channel::<ExportFrame>(EXPORT_QUEUE * NUM_CAMERAS);
+    let handle = thread::Builder::new()
+        .name("export thread".to_string())
+        .spawn(move || {
+            for (file_name, img, y) in rx {
+                if let Err(e) = exporter.add(&file_name, &img, &y) {
+                    error!("Failed to export {}: {}", file_name, e);
+                }
+            }
+            let frames = exporter.frames();
+            let path = exporter.finish().map_err(|e| e.to_string())?;
+            Ok((frames, path))
+        })
+        .map_err(|e| e.to_string())?;
+    *export = Some(ExportWriter {
+        dir: dir.clone(),
+        tx,
+        handle,
+        skipped: 0,
+    });
+    info!("Exporting frames as {:?} to {}.", format, dir);
+    Ok(())
+}
+
+// Stops the export and writes its labels, returns the path of the main label file.
+#[tauri::command]
+pub fn stop_export(state: tauri::State<ModelState>) -> Result<String, String> {
+    let writer = match state.export.lock().unwrap().take() {
+        Some(writer) => writer,
+        None => return Err("Not exporting.".to_string()),
+    };
+    // closing the channel lets the writer finish the queued frames and write the labels
+    drop(writer.tx);
+    let (frames, path) = writer
+        .handle
+        .join()
+        .map_err(|_| "Export thread panicked.".to_string())??;
+    info!(
+        "Exported {} frames to {}, skipped {}.",
+        frames,
+        path.display(),
+        writer.skipped
+    );
+    Ok(path.display().to_string())
+}
+
+// Adds the frames of a batch and their results to the running export, if any.
+fn export_frames(
+    state: &ModelState,
+    seq: u64,
+    imgs: &[DynamicImage],
+    err: &[bool],
+    ys: &[YOLOResult],
+) {
+    if let Some(writer) = state.export.lock().unwrap().as_mut() {
+        for (i, (img, y)) in imgs.iter().zip(ys.iter()).enumerate() {
+            if err[i] {
+                continue;
+            }
+            // never wait on the writer, a frame it has no room for is skipped
+            let frame = (format!("cam{}_{:06}.jpg", i, seq), img.clone(), y.clone());
+            if let Err(mpsc::TrySendError::Full(_)) = writer.tx.try_send(frame) {
+                writer.skipped += 1;
+                warn!(
+                    "Export of {} is behind, skipped frame {} of camera {}.",
+                    writer.dir, seq, i
+                );
+            }
+        }
+    }
+}
+
+// Returns the class names of the running model.
+#[tauri::command]
+pub fn get_class_names(state: tauri::State<ModelState>) -> Result<Vec<String>, String> {
+    match state.active.lock().unwrap().as_ref() {
+        Some(active) => Ok(active.names.clone()),
+        None => Err("No model is running.".to_string()),
+    }
+}
+
+// Returns the thresholds of the running model.
+#[tauri::command]
+pub fn get_thresholds(state: tauri::State<ModelState>) -> Result<Thresholds, String> {
+    match state.active.lock().unwrap().as_ref() {
+        Some(active) => Ok(active.thresholds.clone()),
+        None => Err("No model is running.".to_string()),
+    }
+}
+
+/*
+Hands the thresholds to the inference thread, which validates them against the
+model it is running when it gets to them, applies them before the next batch and
+emits `thresholds-updated`, or `thresholds-error`. With `persist` they are then
+written to that model's own args toml and `thresholds-saved` is emitted.
+*/
+#[tauri::command]
+pub fn set_thresholds(
+    state: tauri::State<ModelState>,
+    thresholds: Thresholds,
+    persist: bool,
+) -> Result<(), String> {
+    send_update(&state, ModelUpdate::Thresholds(thresholds, persist))
+}
+
+// Writes applied thresholds to the args toml of the model they belong to.
+fn persist_thresholds(active: &ActiveModel, thresholds: &Thresholds) -> Result<String, String> {
+    // never the shared default args toml, other models' class names would end up in it
+    let Some(args) = active.args.as_ref() else {
+        return Err(format!(
+            "Thresholds applied but not saved, model {} has no args toml of its own. \
+             Set `args` of its manifest entry.",
+            active.model
+        ));
+    };
+    Args::persist_thresholds(Path::new(args), thresholds).map_err(|e| e.to_string())?;
+    Ok(args.clone())
+}
+
 /*
 Starts the streaming process by setting up the capture threads, model thread,
 and emitter threads. The capture threads grab the frames from the camera and
@@ -69,10 +450,47 @@ their respective emitter threads. The emitter threads convert the frames to
 bytes and send them to the frontend through the window.
 */
 #[tauri::command]
-pub fn start_streaming(window: tauri::Window) {
+pub fn start_streaming(
+    window: tauri::Window,
+    state: tauri::State<ModelState>,
+) -> Result<(), LoadError> {
     info!("Starting streaming...");
 
-    let mut model = YOLOv8::new(Args::new_from_toml(Path::new("./model_args.toml"))).unwrap();
+    let mut args = Args::new_from_toml(Path::new(MODEL_ARGS))?;
+    resolve_self_test_images(&window.app_handle(), &mut args);
+    let model_name = args.model_id.clone().unwrap_or(args.model.clone());
+    // the default args toml belongs to the startup model, unless that is a registered one
+    let own_args = match args.model_id.as_ref() {
+        Some(id) => Manifest::load(Path::new(&args.models_dir))
+            .ok()
+            .and_then(|manifest| {
+                manifest
+                    .get(id)
+                    .ok()
+                    .and_then(|record| record.args_path(Path::new(&args.models_dir)))
+            })
+            .map(|path| path.to_string_lossy().to_string()),
+        None => Some(MODEL_ARGS.to_string()),
+    };
+    let masks = args.detections_masks;
+    state
+        .client_overlay
+        .store(args.client_overlay, Ordering::Relaxed);
+    // extra models stay loaded across swaps of the primary model
+    let mut ensemble = Ensemble::new(&args.ensemble, args.ensemble_mode.clone(), args.weight)
+        .inspect_err(|e| error!("Failed to load the ensemble: {e}"))?;
+    let mut model =
+        YOLOv8::new(args).inspect_err(|e| error!("Failed to load model {model_name}: {e}"))?;
+    if let Some(report) = model.self_test() {
+        window
+            .emit("self-test", report)
+            .expect("Failed to emit self-test.");
+    }
+
+    // model swaps and threshold changes arrive through this channel
+    let (update_tx, update_rx) = mpsc::channel::<ModelUpdate>();
+    *state.update_tx.lock().unwrap() = Some(update_tx);
+    *state.active.lock().unwrap() = Some(ActiveModel::new(own_args, &model));
 
     // setup capture threads
     let frame_recievers = setup_captures(window.clone(), NUM_CAMERAS as i32);
@@ -85,12 +503,62 @@ pub fn start_streaming(window: tauri::Window) {
         .name("inference thread".to_string())
         .spawn(move || {
             info!("Starting multi-camera capture and inference loop...\n");
+            let app = window.app_handle();
+            let state = app.state::<ModelState>();
             let mut loop_count = 0; // for periodic logging
+            let mut seq: u64 = 0; // of the batches, sent with their detections
             loop {
                 let log = loop_count >= LOG_INTERVAL;
 
+                // apply model updates, between batches only
+                for update in update_rx.try_iter() {
+                    match update {
+                        ModelUpdate::Swap(active, new_model) => {
+                            info!("Switching to model {}.", active.model);
+                            model = *new_model;
+                            window
+                                .emit("model-activated", &active.model)
+                                .expect("Failed to emit model-activated.");
+                            *state.active.lock().unwrap() = Some(active);
+                        }
+                        ModelUpdate::Thresholds(thresholds, persist) => {
+                            match model.set_thresholds(thresholds) {
+                                Ok(()) => {
+                                    let thresholds = model.thresholds();
+                                    let mut saved = None;
+                                    if let Some(active) = state.active.lock().unwrap().as_mut() {
+                                        active.thresholds = thresholds.clone();
+                                        if persist {
+                                            saved = Some(persist_thresholds(active, &thresholds));
+                                        }
+                                    }
+                                    window
+                                        .emit("thresholds-updated", &thresholds)
+                                        .expect("Failed to emit thresholds-updated.");
+                                    match saved {
+                                        Some(Ok(path)) => window
+                                            .emit("thresholds-saved", path)
+                                            .expect("Failed to emit thresholds-saved."),
+                                        Some(Err(e)) => window
+                                            .emit("thresholds-error", e)
+                                            .expect("Failed to emit thresholds-error."),
+                                        None => {}
+                                    }
+                                }
+                                Err(e) => {
+                                    window
+                                        .emit("thresholds-error", e.to_string())
+                                        .expect("Failed to emit thresholds-error.");
+                                }
+                            }
+                        }
+                    }
+                }
+
                 let loop_start = Instant::now();
+                seq += 1;
                 let mut imgs = vec![DynamicImage::new_rgb8(0, 0); NUM_CAMERAS];
+                let mut detections: Vec<Option<DetectionsPayload>> = vec![None; NUM_CAMERAS];
                 let mut err = vec![false; NUM_CAMERAS];
 
                 // get a Frame from reciever and update imgs/err appropriately
@@ -113,22 +581,59 @@ pub fn start_streaming(window: tauri::Window) {
                 }
 
                 if INFERENCE {
-                    // run inference
-                    let results = model.run(&imgs, log).expect("valid YOLOResult");
+                    // run inference, plot images unless the frontend draws the detections
+                    let plot = !state.client_overlay.load(Ordering::Relaxed);
+                    let (records, ploted_imgs) = if ensemble.is_empty() {
+                        let results = model.run(&imgs, log).expect("valid YOLOResult");
+                        export_frames(&state, seq, &imgs, &err, &results);
+                        let records: Vec<_> = results
+                            .iter()
+                            .map(|y| vec![y.to_record(model.names(), masks)])
+                            .collect();
+                        (
+                            records,
+                            plot.then(|| model.plot_batch(&results, &imgs[..], log)),
+                        )
+                    } else {
+                        let layers = ensemble
+                            .run(&mut model, &imgs, log)
+                            .expect("valid YOLOResult");
+                        export_frames(&state, seq, &imgs, &err, &layers[0]);
+                        let layers = ensemble.combine(&model, layers);
+                        let records = ensemble.records(&model, &layers, masks);
+                        let ploted_imgs =
+                            plot.then(|| ensemble.plot_batch(&model, &layers, &imgs[..], log));
+                        (records, ploted_imgs)
+                    };
 
-                    // plot images
-                    let ploted_imgs = model.plot_batch(&results, &imgs[..], log);
-
-                    imgs = ploted_imgs
-                        .iter()
-                        .map(|img| DynamicImage::ImageRgb8(img.clone()))
+                    detections = records
+                        .into_iter()
+                        .zip(imgs.iter())
+                        .map(|(results, img)| {
+                            Some(DetectionsPayload {
+                                seq,
+                                width: img.width(),
+                                height: img.height(),
+                                plotted: plot,
+                                results,
+                            })
+                        })
                         .collect();
+                    if let Some(ploted_imgs) = ploted_imgs {
+                        imgs = ploted_imgs
+                            .into_iter()
+                            .map(DynamicImage::ImageRgb8)
+                            .collect();
+                    }
                 }
 
                 for (i, tx) in payload_senders.iter().enumerate() {
                     if !err[i] {
-                        tx.send(imgs[i].clone())
-                            .expect("Failed to send batch to emitter thread.");
+                        tx.send(Frame {
+                            img: imgs[i].clone(),
+                            detections: detections[i].take(),
+                        })
+                        .expect("Failed to send batch to emitter thread.");
                     }
                 }
 
@@ -144,4 +649,6 @@ pub fn start_streaming(window: tauri::Window) {
             }
         })
         .expect("Failed to spawn thread");
+
+    Ok(())
 }
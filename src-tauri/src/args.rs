@@ -1,16 +1,28 @@
+use std::collections::BTreeMap;
 use std::path::Path;
 
+use anyhow::Result;
 use figment::{
     providers::{self, Format},
     Figment,
 };
 
-use crate::YOLOTask;
+use crate::{
+    CpuOptions, EnsembleMember, EnsembleMode, LoadError, MaskEncoding, ModelFamily, OptLevel,
+    PoolOptions, QuantParams, Thresholds, YOLOTask,
+};
 
 #[derive(Debug, Clone, serde::Deserialize)]
 #[serde(default)]
 pub struct Args {
-    /// ONNX model path
+    /// ID of a model registered in the manifest of `models_dir`
+    pub model_id: Option<String>,
+
+    /// directory of the registered models and their `manifest.toml`
+    pub models_dir: String,
+
+    /// ONNX model path, only used when `model_id` is not set; kept so args files from
+    /// before the manifest still load, unverified
     pub model: String,
 
     /// input path
@@ -37,9 +49,60 @@ pub struct Args {
     /// using TensorRT --fp16
     pub fp16: bool,
 
+    /// the model expects pixels in [0, 1], false if it normalizes inside the graph
+    pub normalized_input: bool,
+
+    /// quantization scale of uint8/int8 inputs, 1/255 (or 1 if not normalized) if not set
+    pub input_scale: Option<f32>,
+
+    /// quantization zero point of uint8/int8 inputs, 0 for uint8 and -128 for int8 if not set
+    pub input_zero_point: Option<i32>,
+
+    /// quantization scales of uint8/int8 outputs, by output index
+    pub output_scales: Vec<f32>,
+
+    /// quantization zero points of uint8/int8 outputs, by output index
+    pub output_zero_points: Vec<i32>,
+
+    /// ORT intra-op thread count, ORT default if not set
+    pub intra_threads: Option<i16>,
+
+    /// ORT inter-op thread count, only used with parallel execution
+    pub inter_threads: Option<i16>,
+
+    /// sessions of the model run concurrently, each holding its own copy of the weights
+    pub pool_size: usize,
+
+    /// ORT intra-op thread count of each pooled session, cores / `pool_size` if not set
+    pub pool_threads: Option<i16>,
+
+    /// split batches across the pooled sessions, timed at load time if not set
+    pub pool_split: Option<bool>,
+
+    /// ORT graph optimization level
+    pub opt_level: OptLevel,
+
+    /// run independent graph nodes in parallel instead of sequentially
+    pub parallel: bool,
+
+    /// use the ORT memory arena
+    pub mem_arena: bool,
+
+    /// use ORT memory pattern optimization
+    pub mem_pattern: bool,
+
+    /// model as optimized by ORT, saved on the first load and used in place of `model` after
+    pub optimized_model: Option<String>,
+
+    /// time a few ORT settings at load time and use the fastest
+    pub ort_autotune: bool,
+
     /// specify YOLO task
     pub task: Option<YOLOTask>,
 
+    /// model family of the output layout, guessed from metadata and output shapes if not set
+    pub family: Option<ModelFamily>,
+
     /// num_classes
     pub nc: Option<u32>,
 
@@ -49,6 +112,9 @@ pub struct Args {
     /// num_masks
     pub nm: Option<u32>,
 
+    /// Ultralytics data.yaml or text file of class names, overrides the model metadata
+    pub labels: Option<String>,
+
     /// input image width
     pub width: Option<u32>,
 
@@ -64,9 +130,54 @@ pub struct Args {
     /// confidence threshold of keypoint
     pub kconf: f32,
 
+    /// per-class confidence thresholds by class name, overriding `conf`
+    pub class_conf: BTreeMap<String, f32>,
+
+    /// class names that are never reported
+    pub hidden_classes: Vec<String>,
+
+    /// skeleton edges as pairs of keypoint indices, overrides model metadata
+    pub skeleton: Option<Vec<[usize; 2]>>,
+
+    /// number of classes reported and drawn for classification
+    pub topk: usize,
+
+    /// test-time augmentation, detection only
+    pub tta: bool,
+
+    /// image scales of test-time augmentation, in (0, 1]
+    pub tta_scales: Vec<f32>,
+
+    /// also run a horizontal flip of every scale
+    pub tta_flip: bool,
+
+    /// extra models to run on the same batch
+    pub ensemble: Vec<EnsembleMember>,
+
+    /// fuse the ensemble results or draw each model as a layer
+    pub ensemble_mode: EnsembleMode,
+
+    /// weight of this model in weighted box fusion
+    pub weight: f32,
+
+    /// synthetic batches run at load time
+    pub warmup: u32,
+
+    /// run the self-test images through the model at load time
+    pub self_test: bool,
+
+    /// directory of the self-test images and their expected.toml
+    pub self_test_images: String,
+
     /// plot inference result and save
     pub plot: bool,
 
+    /// send plain frames and let the frontend draw the detections from `detections-{i}` events
+    pub client_overlay: bool,
+
+    /// encoding of the masks in `detections-{i}` events: none, rle or polygons
+    pub detections_masks: MaskEncoding,
+
     /// check time consumed in each stage
     pub profile: bool,
 }
@@ -74,35 +185,120 @@ pub struct Args {
 impl Default for Args {
     fn default() -> Self {
         Args {
-            model: "./models/yolov8n.onnx".to_string(), // ONNX model path
-            source: "".to_string(),                     // Input path
-            device_id: 0,                               // device id
-            trt: false,                                 // Enable if using TensorRT
-            cuda: true,                                 // Enable if using CUDA
-            batch: 3,                                   // Set the batch size to 3
-            batch_min: 1,                               // If using TensorRT, min_batch size
-            batch_max: 3,                               // If using TensorRT, max_batch size
+            model_id: None,                                     // Registered model ID
+            models_dir: "./models".to_string(),                 // Registered models and manifest
+            model: "./models/yolov8n.onnx".to_string(),         // ONNX model path
+            source: "".to_string(),                             // Input path
+            device_id: 0,                                       // device id
+            trt: false,                                         // Enable if using TensorRT
+            cuda: true,                                         // Enable if using CUDA
+            batch: 3,                                           // Set the batch size to 3
+            batch_min: 1,                                       // If using TensorRT, min_batch size
+            batch_max: 3,                                       // If using TensorRT, max_batch size
             fp16: false, // Enable if you want to use FP16 precision with TensorRT
+            normalized_input: true, // Model input in [0, 1]
+            input_scale: None, // Quantized input scale
+            input_zero_point: None, // Quantized input zero point
+            output_scales: vec![], // Quantized output scales
+            output_zero_points: vec![], // Quantized output zero points
+            intra_threads: None, // ORT intra-op threads
+            inter_threads: None, // ORT inter-op threads
+            pool_size: 1, // ORT sessions run concurrently
+            pool_threads: None, // ORT intra-op threads per pooled session
+            pool_split: None, // Split batches across the pool, timed if not set
+            opt_level: OptLevel::All, // ORT graph optimization level
+            parallel: false, // ORT parallel execution
+            mem_arena: true, // ORT memory arena
+            mem_pattern: true, // ORT memory pattern
+            optimized_model: None, // Optimized model, saved on first load
+            ort_autotune: false, // Pick the fastest ORT settings at load time
             task: Some(YOLOTask::Detect), // Define the task
+            family: None, // Output layout: yolov5, yolov8, yolov10 or yolo11
             nc: Some(5), // Number of classes
             nk: None,    // Number of keypoints
             nm: None,    // Number of masks
+            labels: None, // Class names file, names from metadata if not set
             width: Some(640), // Input image width for YOLO model
             height: Some(640), // Input image height for YOLO model
             conf: 0.5,   // Confidence threshold for detections
             iou: 0.5,    // IoU threshold for Non-Max Suppression
             kconf: 0.5,  // Keypoint confidence threshold (if keypoints are used)
+            class_conf: BTreeMap::new(), // Per-class confidence thresholds
+            hidden_classes: vec![], // Classes to drop from the results
+            skeleton: None, // Skeleton edges, taken from metadata or nk if not set
+            topk: 5,     // Top-k classes of a classification result
+            tta: false,  // Test-time augmentation
+            tta_scales: vec![1.0, 0.83, 0.67], // Scales of test-time augmentation
+            tta_flip: true, // Flipped copies in test-time augmentation
+            ensemble: vec![], // Extra models, each with its own args toml
+            ensemble_mode: EnsembleMode::Layers, // How ensemble results are combined
+            weight: 1.0, // Weight in weighted box fusion
+            warmup: 3,   // Synthetic batches at load time
+            self_test: true, // Self-test with the bundled images at load time
+            self_test_images: "./resources/images".to_string(), // Self-test images
             plot: false, // Enable plotting results
+            client_overlay: false, // Frontend draws the detections
+            detections_masks: MaskEncoding::None, // Masks in detections events
             profile: false, // Enable profiling if needed
         }
     }
 }
 
 impl Args {
-    pub fn new_from_toml(toml: &Path) -> Self {
-        Figment::new()
+    pub fn new_from_toml(toml: &Path) -> Result<Self, LoadError> {
+        Ok(Figment::new()
             .merge(providers::Toml::file(toml))
-            .extract()
-            .expect("to be valid")
+            .extract()?)
+    }
+
+    pub fn cpu_options(&self) -> CpuOptions {
+        CpuOptions {
+            intra_threads: self.intra_threads,
+            inter_threads: self.inter_threads,
+            opt_level: self.opt_level,
+            parallel: self.parallel,
+            mem_arena: self.mem_arena,
+            mem_pattern: self.mem_pattern,
+        }
+    }
+
+    pub fn pool_options(&self) -> PoolOptions {
+        PoolOptions {
+            size: self.pool_size,
+            threads: self.pool_threads,
+            split: self.pool_split,
+        }
+    }
+
+    pub fn output_quant(&self) -> Vec<QuantParams> {
+        self.output_scales
+            .iter()
+            .enumerate()
+            .map(|(i, &scale)| QuantParams {
+                scale,
+                zero_point: self.output_zero_points.get(i).copied().unwrap_or(0),
+            })
+            .collect()
+    }
+
+    pub fn thresholds(&self) -> Thresholds {
+        Thresholds {
+            conf: self.conf,
+            iou: self.iou,
+            kconf: self.kconf,
+            class_conf: self.class_conf.clone(),
+            hidden_classes: self.hidden_classes.clone(),
+        }
+    }
+
+    // Writes the thresholds into a toml file, keeping every other field as it is.
+    pub fn persist_thresholds(path: &Path, thresholds: &Thresholds) -> Result<()> {
+        let mut table = match std::fs::read_to_string(path) {
+            Ok(content) => content.parse::<toml::Table>()?,
+            Err(_) => toml::Table::new(),
+        };
+        table.extend(toml::Table::try_from(thresholds)?);
+        std::fs::write(path, toml::to_string_pretty(&table)?)?;
+        Ok(())
     }
 }
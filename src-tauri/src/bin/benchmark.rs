@@ -0,0 +1,319 @@
+// Runs a model headless over a folder of images or a video and reports the latency of every
+// stage of the streaming loop, as JSON and Markdown, optionally compared against a baseline.
+//
+// cargo run --release --bin benchmark -- --source ./resources/images --iterations 200 \
+//     --json performance/report.json --markdown performance/report.md \
+//     --baseline performance/baseline.json
+
+use anyhow::{bail, Result};
+use clap::Parser;
+use image::{DynamicImage, ImageFormat};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use app::export::read_source;
+use app::multi_capture::convert_to_bytes;
+use app::{gen_time_string, Args, YOLOv8};
+
+// bumped when fields of the report change meaning
+const REPORT_VERSION: u32 = 1;
+// the format the emitters send frames in
+const IMAGE_FORMAT: ImageFormat = ImageFormat::Bmp;
+const STAGES: [&str; 6] = [
+    "preprocess",
+    "ort",
+    "postprocess",
+    "plot",
+    "encode",
+    "total",
+];
+
+#[derive(Parser, Debug)]
+#[command(about = "Per-stage latency benchmark of a model, without the UI")]
+struct Cli {
+    /// args toml of the model
+    #[arg(long, default_value = "./model_args.toml")]
+    args: String,
+
+    /// folder of images or a video file
+    #[arg(long)]
+    source: String,
+
+    /// timed batches
+    #[arg(long, default_value_t = 100)]
+    iterations: u32,
+
+    /// batches run before the timed ones
+    #[arg(long, default_value_t = 5)]
+    warmup: u32,
+
+    /// frames per batch, the batch of the args toml if not set
+    #[arg(long)]
+    batch: Option<u32>,
+
+    /// ORT intra-op thread count
+    #[arg(long)]
+    intra_threads: Option<i16>,
+
+    /// ORT inter-op thread count
+    #[arg(long)]
+    inter_threads: Option<i16>,
+
+    /// ORT sessions run concurrently
+    #[arg(long)]
+    pool_size: Option<usize>,
+
+    /// ORT intra-op thread count of each pooled session
+    #[arg(long)]
+    pool_threads: Option<i16>,
+
+    /// frames read from a video at most
+    #[arg(long, default_value_t = 300)]
+    max_frames: usize,
+
+    /// where to write the JSON report
+    #[arg(long)]
+    json: Option<String>,
+
+    /// where to write the Markdown report
+    #[arg(long)]
+    markdown: Option<String>,
+
+    /// JSON report to compare against
+    #[arg(long)]
+    baseline: Option<String>,
+
+    /// slowdown of a stage's p50 or p90 against the baseline that counts as a regression
+    #[arg(long, default_value_t = 0.1)]
+    threshold: f64,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+struct Report {
+    version: u32,
+    created: String,
+    model: String,
+    source: String,
+    frames: usize,
+    batch: u32,
+    iterations: u32,
+    intra_threads: Option<i16>,
+    inter_threads: Option<i16>,
+    pool_size: usize,
+    fps: f64,
+    stages: Vec<Stage>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+struct Stage {
+    // latencies of one stage per batch, in milliseconds
+    name: String,
+    mean_ms: f64,
+    min_ms: f64,
+    p50_ms: f64,
+    p90_ms: f64,
+    p99_ms: f64,
+    max_ms: f64,
+}
+
+impl Stage {
+    fn new(name: &str, times: &[Duration]) -> Self {
+        let mut ms: Vec<f64> = times.iter().map(|t| t.as_secs_f64() * 1000.).collect();
+        ms.sort_by(|a, b| a.total_cmp(b));
+        // nearest rank
+        let percentile =
+            |p: f64| ms[((p * ms.len() as f64).ceil() as usize).clamp(1, ms.len()) - 1];
+        Self {
+            name: name.to_string(),
+            mean_ms: ms.iter().sum::<f64>() / ms.len() as f64,
+            min_ms: ms[0],
+            p50_ms: percentile(0.5),
+            p90_ms: percentile(0.9),
+            p99_ms: percentile(0.99),
+            max_ms: ms[ms.len() - 1],
+        }
+    }
+}
+
+fn markdown(report: &Report, baseline: Option<&Report>, threshold: f64) -> (String, bool) {
+    let mut md = format!(
+        "# Benchmark of {}\n\n{} frames of `{}`, batch {}, {} iterations, intra-op threads {:?}, \
+         inter-op threads {:?}, {} session(s), created {}.\n\n{:.1} frames/s\n\n",
+        report.model,
+        report.frames,
+        report.source,
+        report.batch,
+        report.iterations,
+        report.intra_threads,
+        report.inter_threads,
+        report.pool_size,
+        report.created,
+        report.fps,
+    );
+    md.push_str("| stage | mean ms | min ms | p50 ms | p90 ms | p99 ms | max ms |\n");
+    md.push_str("|---|---|---|---|---|---|---|\n");
+    for s in report.stages.iter() {
+        md.push_str(&format!(
+            "| {} | {:.2} | {:.2} | {:.2} | {:.2} | {:.2} | {:.2} |\n",
+            s.name, s.mean_ms, s.min_ms, s.p50_ms, s.p90_ms, s.p99_ms, s.max_ms
+        ));
+    }
+
+    let Some(baseline) = baseline else {
+        return (md, false);
+    };
+    md.push_str(&format!(
+        "\n## Against the baseline of {}\n\nModel {}, batch {}. A stage regresses when its p50 \
+         or p90 is more than {:.0}% slower.\n\n",
+        baseline.created,
+        baseline.model,
+        baseline.batch,
+        threshold * 100.
+    ));
+    md.push_str("| stage | baseline p50 | p50 | change | baseline p90 | p90 | change | |\n");
+    md.push_str("|---|---|---|---|---|---|---|---|\n");
+    let change = |base: f64, now: f64| if base > 0. { now / base - 1. } else { 0. };
+    let mut regressed = false;
+    for s in report.stages.iter() {
+        let Some(b) = baseline.stages.iter().find(|b| b.name == s.name) else {
+            continue;
+        };
+        let (p50, p90) = (change(b.p50_ms, s.p50_ms), change(b.p90_ms, s.p90_ms));
+        let regression = p50 > threshold || p90 > threshold;
+        regressed |= regression;
+        md.push_str(&format!(
+            "| {} | {:.2} | {:.2} | {:+.1}% | {:.2} | {:.2} | {:+.1}% | {} |\n",
+            s.name,
+            b.p50_ms,
+            s.p50_ms,
+            p50 * 100.,
+            b.p90_ms,
+            s.p90_ms,
+            p90 * 100.,
+            if regression { "REGRESSION" } else { "" }
+        ));
+    }
+    (md, regressed)
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    if cli.iterations == 0 {
+        bail!("--iterations must be at least 1");
+    }
+
+    // the args toml with the settings under test
+    let mut args = Args::new_from_toml(Path::new(&cli.args))?;
+    if let Some(batch) = cli.batch {
+        args.batch = batch;
+        args.batch_min = args.batch_min.min(batch);
+        args.batch_max = args.batch_max.max(batch);
+    }
+    args.intra_threads = cli.intra_threads.or(args.intra_threads);
+    args.inter_threads = cli.inter_threads.or(args.inter_threads);
+    args.pool_size = cli.pool_size.unwrap_or(args.pool_size);
+    args.pool_threads = cli.pool_threads.or(args.pool_threads);
+    args.warmup = 0;
+    args.self_test = false;
+    args.plot = false;
+    args.profile = false;
+    let (intra_threads, inter_threads, pool_size) =
+        (args.intra_threads, args.inter_threads, args.pool_size);
+    let mut model = YOLOv8::new(args)?;
+
+    // every frame is reused across the iterations, so all of them are kept
+    let frames = read_source(&cli.source, cli.max_frames)?
+        .map(|frame| frame.map(|(_, img)| img))
+        .collect::<Result<Vec<_>>>()?;
+    if frames.is_empty() {
+        bail!("No frames in {}", cli.source);
+    }
+    let batch = model.batch() as usize;
+    println!(
+        "Benchmarking {} on {} frames, batch {batch}, {} iterations...",
+        model.model_id(),
+        frames.len(),
+        cli.iterations
+    );
+
+    // the stages of the streaming loop, over the frames in turn
+    let mut times: Vec<Vec<Duration>> = vec![vec![]; STAGES.len()];
+    let mut frame_cycle = frames.iter().cycle();
+    for i in 0..cli.warmup + cli.iterations {
+        let xs: Vec<DynamicImage> = frame_cycle.by_ref().take(batch).cloned().collect();
+        let start = Instant::now();
+        let ys = model.run(&xs, false)?;
+        let run_times = model.times();
+
+        let t_plot = Instant::now();
+        let plotted = model.plot_batch(&ys, &xs, false);
+        let plot_time = t_plot.elapsed();
+
+        let t_encode = Instant::now();
+        for img in plotted {
+            convert_to_bytes(&DynamicImage::ImageRgb8(img), IMAGE_FORMAT);
+        }
+        let encode_time = t_encode.elapsed();
+
+        if i >= cli.warmup {
+            let stage_times = [
+                run_times.preprocess,
+                run_times.inference,
+                run_times.postprocess,
+                plot_time,
+                encode_time,
+                start.elapsed(),
+            ];
+            for (times, t) in times.iter_mut().zip(stage_times) {
+                times.push(t);
+            }
+        }
+    }
+
+    let stages: Vec<Stage> = STAGES
+        .iter()
+        .zip(times.iter())
+        .map(|(name, times)| Stage::new(name, times))
+        .collect();
+    let total_ms = stages[STAGES.len() - 1].mean_ms;
+    let report = Report {
+        version: REPORT_VERSION,
+        created: gen_time_string("-"),
+        model: model.model_id().to_string(),
+        source: cli.source.clone(),
+        frames: frames.len(),
+        batch: batch as u32,
+        iterations: cli.iterations,
+        intra_threads,
+        inter_threads,
+        pool_size,
+        fps: batch as f64 * 1000. / total_ms,
+        stages,
+    };
+
+    let baseline: Option<Report> = match &cli.baseline {
+        Some(path) => {
+            let baseline: Report = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+            if baseline.version != REPORT_VERSION {
+                bail!(
+                    "Baseline {path} is a version {} report, expected {REPORT_VERSION}",
+                    baseline.version
+                );
+            }
+            Some(baseline)
+        }
+        None => None,
+    };
+    let (md, regressed) = markdown(&report, baseline.as_ref(), cli.threshold);
+    println!("{md}");
+    if let Some(path) = &cli.json {
+        std::fs::write(path, serde_json::to_string_pretty(&report)?)?;
+    }
+    if let Some(path) = &cli.markdown {
+        std::fs::write(path, &md)?;
+    }
+    if regressed {
+        bail!("Regression against {}", cli.baseline.unwrap_or_default());
+    }
+    Ok(())
+}
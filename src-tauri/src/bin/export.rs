@@ -0,0 +1,86 @@
+// Runs a model over a folder of images or a recording and exports the detections,
+// with the frames, for evaluation or labelling.
+//
+// cargo run --release --bin export -- --source ./resources/images --output ./export \
+//     --format coco-annotations --masks polygons
+
+use anyhow::{bail, Result};
+use clap::Parser;
+use std::path::Path;
+
+use app::export::read_source;
+use app::{Args, ExportFormat, Exporter, MaskEncoding, YOLOv8};
+
+#[derive(Parser, Debug)]
+#[command(about = "Export the detections of a model over images or a video")]
+struct Cli {
+    /// args toml of the model
+    #[arg(long, default_value = "./model_args.toml")]
+    args: String,
+
+    /// folder of images or a video file
+    #[arg(long)]
+    source: String,
+
+    /// directory to write the frames and labels to
+    #[arg(long)]
+    output: String,
+
+    /// format of the labels
+    #[arg(long, value_enum, default_value_t = ExportFormat::default())]
+    format: ExportFormat,
+
+    /// encoding of segmentation masks
+    #[arg(long, value_enum, default_value_t = MaskEncoding::Rle)]
+    masks: MaskEncoding,
+
+    /// frames read from a video at most
+    #[arg(long, default_value_t = 1000)]
+    max_frames: usize,
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    let mut args = Args::new_from_toml(Path::new(&cli.args))?;
+    args.plot = false;
+    args.profile = false;
+    let mut model = YOLOv8::new(args)?;
+
+    let mut frames = read_source(&cli.source, cli.max_frames)?.peekable();
+    if frames.peek().is_none() {
+        bail!("No frames in {}", cli.source);
+    }
+    let mut exporter = Exporter::new(
+        Path::new(&cli.output),
+        cli.format,
+        model.names().clone(),
+        cli.masks,
+    )?;
+
+    // batches of the model's batch size, read as they are run
+    let batch = model.batch() as usize;
+    loop {
+        let chunk = frames.by_ref().take(batch).collect::<Result<Vec<_>>>()?;
+        if chunk.is_empty() {
+            break;
+        }
+        let (files, mut imgs): (Vec<_>, Vec<_>) = chunk.into_iter().unzip();
+        // the last one filled up with copies, their results are dropped
+        let last = imgs[imgs.len() - 1].clone();
+        imgs.resize(batch, last);
+        let ys = model.run(&imgs, false)?;
+        for ((file, img), y) in files.iter().zip(imgs.iter()).zip(ys.iter()) {
+            exporter.add(file, img, y)?;
+        }
+    }
+
+    let frames = exporter.frames();
+    let path = exporter.finish()?;
+    println!(
+        "Exported {frames} frames of {} to {}",
+        model.model_id(),
+        path.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,275 @@
+use anyhow::Result;
+use image::{DynamicImage, ImageBuffer};
+use log::info;
+use rayon::prelude::*;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::model::draw_bboxes;
+use crate::{
+    check_font, weighted_boxes_fusion, Args, Bbox, LoadError, MaskEncoding, ResultRecord,
+    YOLOResult, YOLOv8,
+};
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct EnsembleMember {
+    // an extra model that runs on the same batch as the primary one
+    /// args toml of the model, with its own thresholds
+    pub args: String,
+
+    /// weight in weighted box fusion
+    #[serde(default = "default_weight")]
+    pub weight: f32,
+
+    /// class name of this model -> class name in the fused results
+    #[serde(default)]
+    pub class_map: BTreeMap<String, String>,
+}
+
+fn default_weight() -> f32 {
+    1.0
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EnsembleMode {
+    // merge the bboxes of all models with weighted box fusion
+    Fuse,
+    // keep the results of every model and draw them on top of each other
+    #[default]
+    Layers,
+}
+
+struct Member {
+    model: YOLOv8,
+    weight: f32,
+    class_map: BTreeMap<String, String>,
+}
+
+pub struct Ensemble {
+    // models running next to the primary model, which stays outside so it can be swapped
+    members: Vec<Member>,
+    mode: EnsembleMode,
+    weight: f32,
+}
+
+impl Ensemble {
+    pub fn new(
+        config: &[EnsembleMember],
+        mode: EnsembleMode,
+        weight: f32,
+    ) -> Result<Self, LoadError> {
+        let mut members = vec![];
+        for member in config.iter() {
+            info!("Loading ensemble model from {}...", member.args);
+            members.push(Member {
+                model: YOLOv8::new(Args::new_from_toml(Path::new(&member.args))?)?,
+                weight: member.weight,
+                class_map: member.class_map.clone(),
+            });
+        }
+        Ok(Self {
+            members,
+            mode,
+            weight,
+        })
+    }
+
+    pub fn mode(&self) -> &EnsembleMode {
+        &self.mode
+    }
+
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    pub fn run(
+        &mut self,
+        primary: &mut YOLOv8,
+        xs: &[DynamicImage],
+        log: bool,
+    ) -> Result<Vec<Vec<YOLOResult>>> {
+        // results of every model: the primary first, then the members in config order
+        let mut layers = vec![primary.run(xs, log)?];
+        for member in self.members.iter_mut() {
+            layers.push(member.model.run(xs, log)?);
+        }
+        Ok(layers)
+    }
+
+    pub fn classes(&self, primary: &YOLOv8) -> (Vec<String>, Vec<(u8, u8, u8)>, Vec<Vec<usize>>) {
+        // Class names of the fused results: those of the primary model, then every
+        // (remapped) class of the members that is not there yet, each keeping the color
+        // of the model that introduced it. Also returns the class id mapping of each model.
+        let mut names = primary.names().clone();
+        let mut palette = primary.color_palette().clone();
+        let mut id_maps = vec![(0..names.len()).collect::<Vec<_>>()];
+        for member in self.members.iter() {
+            let mut id_map = vec![];
+            for (name, &color) in member
+                .model
+                .names()
+                .iter()
+                .zip(member.model.color_palette().iter())
+            {
+                let name = member.class_map.get(name).unwrap_or(name);
+                let id = match names.iter().position(|n| n == name) {
+                    Some(id) => id,
+                    None => {
+                        names.push(name.clone());
+                        palette.push(color);
+                        names.len() - 1
+                    }
+                };
+                id_map.push(id);
+            }
+            id_maps.push(id_map);
+        }
+        (names, palette, id_maps)
+    }
+
+    pub fn fuse(&self, primary: &YOLOv8, layers: &[Vec<YOLOResult>]) -> Vec<YOLOResult> {
+        // one result per image with the bboxes of all models, fused and in the unified classes
+        let (_, _, id_maps) = self.classes(primary);
+        let weights: Vec<f32> = std::iter::once(self.weight)
+            .chain(self.members.iter().map(|m| m.weight))
+            .collect();
+        let iou = primary.iou();
+        // fused results come from all models together
+        let model_id: Arc<str> = std::iter::once(primary.model_id())
+            .chain(self.members.iter().map(|m| m.model.model_id()))
+            .collect::<Vec<_>>()
+            .join("+")
+            .into();
+
+        (0..layers[0].len())
+            .into_par_iter()
+            .map(|idx| {
+                let bboxes: Vec<_> = layers
+                    .iter()
+                    .zip(id_maps.iter())
+                    .map(|(ys, id_map)| {
+                        ys[idx]
+                            .bboxes()
+                            .map(|bboxes| {
+                                bboxes
+                                    .iter()
+                                    .map(|b| {
+                                        Bbox::new(
+                                            b.xmin(),
+                                            b.ymin(),
+                                            b.width(),
+                                            b.height(),
+                                            id_map[b.id()],
+                                            b.confidence(),
+                                        )
+                                    })
+                                    .collect()
+                            })
+                            .unwrap_or_default()
+                    })
+                    .collect();
+                let fused = weighted_boxes_fusion(&bboxes, &weights, iou);
+                YOLOResult {
+                    bboxes: if !fused.is_empty() { Some(fused) } else { None },
+                    model_id: Some(Arc::clone(&model_id)),
+                    ..Default::default()
+                }
+            })
+            .collect()
+    }
+
+    pub fn combine(&self, primary: &YOLOv8, layers: Vec<Vec<YOLOResult>>) -> Vec<Vec<YOLOResult>> {
+        // the layers of `records` and `plot_batch`: those of every model, or the fused
+        // results as the only layer, so a batch is fused once for both
+        match self.mode {
+            EnsembleMode::Fuse => vec![self.fuse(primary, &layers)],
+            EnsembleMode::Layers => layers,
+        }
+    }
+
+    pub fn records(
+        &self,
+        primary: &YOLOv8,
+        layers: &[Vec<YOLOResult>],
+        masks: MaskEncoding,
+    ) -> Vec<Vec<ResultRecord>> {
+        // per image, what `plot_batch` draws: the fused results, or those of every model in turn
+        match self.mode {
+            EnsembleMode::Fuse => {
+                let (names, _, _) = self.classes(primary);
+                layers[0]
+                    .iter()
+                    .map(|y| vec![y.to_record(&names, masks)])
+                    .collect()
+            }
+            EnsembleMode::Layers => {
+                let models: Vec<&YOLOv8> = std::iter::once(primary)
+                    .chain(self.members.iter().map(|m| &m.model))
+                    .collect();
+                (0..layers[0].len())
+                    .map(|idx| {
+                        models
+                            .iter()
+                            .zip(layers.iter())
+                            .map(|(model, ys)| ys[idx].to_record(model.names(), masks))
+                            .collect()
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    pub fn plot_batch(
+        &self,
+        primary: &YOLOv8,
+        layers: &[Vec<YOLOResult>],
+        xs0: &[DynamicImage],
+        log: bool,
+    ) -> Vec<ImageBuffer<image::Rgb<u8>, Vec<u8>>> {
+        let start = Instant::now();
+        let imgs = match self.mode {
+            EnsembleMode::Fuse => {
+                let (names, palette, _) = self.classes(primary);
+                let font = check_font("./fonts/Arial.ttf");
+                layers[0]
+                    .par_iter()
+                    .zip(xs0.par_iter())
+                    .map(|(y, img0)| {
+                        let mut img = img0.to_rgb8();
+                        if let Some(bboxes) = y.bboxes() {
+                            draw_bboxes(&mut img, bboxes, &names, &palette, &font);
+                        }
+                        img
+                    })
+                    .collect()
+            }
+            EnsembleMode::Layers => {
+                // every model draws its own results, with its own colors, over the previous one
+                let models: Vec<&YOLOv8> = std::iter::once(primary)
+                    .chain(self.members.iter().map(|m| &m.model))
+                    .collect();
+                xs0.par_iter()
+                    .enumerate()
+                    .map(|(idx, img0)| {
+                        let mut img = img0.to_rgb8();
+                        for (model, ys) in models.iter().zip(layers.iter()) {
+                            img = model.plot(&ys[idx], &DynamicImage::ImageRgb8(img));
+                        }
+                        img
+                    })
+                    .collect()
+            }
+        };
+        if log {
+            info!("Ensemble plot_batch duration: {:?}", start.elapsed());
+        }
+        imgs
+    }
+}
@@ -0,0 +1,481 @@
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use image::DynamicImage;
+use std::path::{Path, PathBuf};
+
+use crate::multi_capture::VideoFrames;
+use crate::{Bbox, MaskEncoding, Point2, YOLOResult};
+
+// where the exported frames go, inside the export directory
+pub const IMAGES_DIR: &str = "images";
+const COCO_RESULTS: &str = "results.json";
+const COCO_ANNOTATIONS: &str = "annotations.json";
+const COCO_IMAGES: &str = "images.json";
+const YOLO_LABELS_DIR: &str = "labels";
+const VOC_ANNOTATIONS_DIR: &str = "annotations";
+const DATA_YAML: &str = "data.yaml";
+const IMAGE_EXTENSIONS: [&str; 4] = ["jpg", "jpeg", "png", "bmp"];
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum ExportFormat {
+    // a COCO results list, to evaluate against ground truth with `COCO.loadRes`
+    #[default]
+    CocoResults,
+    // a COCO dataset with images, categories and the detections as annotations
+    CocoAnnotations,
+    // Ultralytics `.txt` labels under `labels/` and a `data.yaml`
+    Yolo,
+    // Pascal VOC `.xml` annotations under `annotations/` and a `data.yaml`
+    Voc,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+struct CocoImage {
+    id: u64,
+    file_name: String,
+    width: u32,
+    height: u32,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+struct CocoCategory {
+    id: usize,
+    name: String,
+    supercategory: String,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(untagged)]
+enum CocoSegmentation {
+    // compressed RLE, or flat [x1, y1, x2, y2, ...] polygons
+    Rle { size: [u32; 2], counts: String },
+    Polygons(Vec<Vec<f32>>),
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+struct CocoAnnotation {
+    id: u64,
+    image_id: u64,
+    category_id: usize,
+    bbox: [f32; 4], // [x, y, width, height]
+    area: f32,
+    iscrowd: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    score: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    segmentation: Option<CocoSegmentation>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    keypoints: Option<Vec<f32>>, // [x1, y1, v1, ...]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    num_keypoints: Option<usize>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+struct CocoDataset<'a> {
+    images: &'a Vec<CocoImage>,
+    annotations: &'a Vec<CocoAnnotation>,
+    categories: Vec<CocoCategory>,
+}
+
+/*
+Collects frames and their results and writes them out in one of the `ExportFormat`s.
+Frames are saved under `images/` in the export directory. YOLO and VOC labels are
+written per frame as it is added, COCO files and the `data.yaml` by `finish`.
+Image and annotation ids count from 1, and a class id `i` of the model is COCO
+category `i + 1`, as category 0 is taken to be background. YOLO and VOC keep the
+class ids of the model.
+*/
+pub struct Exporter {
+    dir: PathBuf,
+    format: ExportFormat,
+    names: Vec<String>,
+    masks: MaskEncoding,
+    images: Vec<CocoImage>,
+    annotations: Vec<CocoAnnotation>,
+    // keypoints per instance seen so far, the `kpt_shape` of the data.yaml
+    kpts: Option<usize>,
+}
+
+impl Exporter {
+    pub fn new(
+        dir: &Path,
+        format: ExportFormat,
+        names: Vec<String>,
+        masks: MaskEncoding,
+    ) -> Result<Self> {
+        std::fs::create_dir_all(dir.join(IMAGES_DIR))?;
+        match format {
+            ExportFormat::Yolo => std::fs::create_dir_all(dir.join(YOLO_LABELS_DIR))?,
+            ExportFormat::Voc => std::fs::create_dir_all(dir.join(VOC_ANNOTATIONS_DIR))?,
+            _ => {}
+        }
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            format,
+            names,
+            masks,
+            images: vec![],
+            annotations: vec![],
+            kpts: None,
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn format(&self) -> ExportFormat {
+        self.format
+    }
+
+    pub fn frames(&self) -> usize {
+        self.images.len()
+    }
+
+    // Saves a frame as `images/{file_name}` and adds its detections.
+    pub fn add(&mut self, file_name: &str, img: &DynamicImage, y: &YOLOResult) -> Result<()> {
+        img.save(self.dir.join(IMAGES_DIR).join(file_name))?;
+        let image_id = self.images.len() as u64 + 1;
+        self.images.push(CocoImage {
+            id: image_id,
+            file_name: file_name.to_string(),
+            width: img.width(),
+            height: img.height(),
+        });
+
+        if let Some(kpts) = y.keypoints().and_then(|kpts| kpts.first()) {
+            self.kpts = Some(kpts.len());
+        }
+
+        let stem = Path::new(file_name)
+            .file_stem()
+            .map_or(file_name.to_string(), |s| s.to_string_lossy().to_string());
+        match self.format {
+            ExportFormat::CocoResults | ExportFormat::CocoAnnotations => {
+                self.add_coco(image_id, y);
+            }
+            ExportFormat::Yolo => {
+                let labels = self.yolo_labels(y, img.width() as f32, img.height() as f32);
+                let path = self.dir.join(YOLO_LABELS_DIR).join(stem + ".txt");
+                std::fs::write(path, labels)?;
+            }
+            ExportFormat::Voc => {
+                let xml = self.voc_annotation(file_name, y, img.width(), img.height());
+                let path = self.dir.join(VOC_ANNOTATIONS_DIR).join(stem + ".xml");
+                std::fs::write(path, xml)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn add_coco(&mut self, image_id: u64, y: &YOLOResult) {
+        let Some(bboxes) = y.bboxes() else {
+            return;
+        };
+        // `COCO.loadRes` reads nothing but compressed RLE from a results list
+        let encoding = match (self.format, self.masks) {
+            (ExportFormat::CocoResults, MaskEncoding::Polygons) => MaskEncoding::Rle,
+            (_, masks) => masks,
+        };
+        let segmentations: Option<Vec<(CocoSegmentation, f32)>> = match (encoding, y.masks()) {
+            (MaskEncoding::Rle, Some(masks)) => Some(
+                masks
+                    .rle()
+                    .into_iter()
+                    .map(|rle| {
+                        let area = rle.area() as f32;
+                        let segmentation = CocoSegmentation::Rle {
+                            size: [rle.height(), rle.width()],
+                            counts: rle.compressed(),
+                        };
+                        (segmentation, area)
+                    })
+                    .collect(),
+            ),
+            (MaskEncoding::Polygons, Some(masks)) => Some(
+                masks
+                    .decode()
+                    .iter()
+                    .zip(masks.polygons())
+                    .zip(bboxes.iter())
+                    .map(|((mask, polygons), bbox)| {
+                        // pycocotools needs at least 3 points a polygon
+                        let mut polygons: Vec<Vec<f32>> = polygons
+                            .iter()
+                            .filter(|p| p.len() >= 3)
+                            .map(|p| p.iter().flat_map(|p| [p.x(), p.y()]).collect())
+                            .collect();
+                        // and fails on an empty list, a mask without any is labelled with its box
+                        if polygons.is_empty() {
+                            let (x1, y1, x2, y2) =
+                                (bbox.xmin(), bbox.ymin(), bbox.xmax(), bbox.ymax());
+                            polygons.push(vec![x1, y1, x2, y1, x2, y2, x1, y2]);
+                            return (CocoSegmentation::Polygons(polygons), bbox.area());
+                        }
+                        (CocoSegmentation::Polygons(polygons), mask.area() as f32)
+                    })
+                    .collect(),
+            ),
+            _ => None,
+        };
+        let results = self.format == ExportFormat::CocoResults;
+        for (i, bbox) in bboxes.iter().enumerate() {
+            let (segmentation, area) = match segmentations.as_ref().and_then(|s| s.get(i)) {
+                Some((segmentation, area)) => (Some(segmentation.clone()), *area),
+                None => (None, bbox.area()),
+            };
+            // points of (0, 0) are below the keypoint confidence, they are not labelled
+            let keypoints: Option<Vec<f32>> =
+                y.keypoints().and_then(|kpts| kpts.get(i)).map(|kpts| {
+                    kpts.iter()
+                        .flat_map(|p| match p.x() != 0. || p.y() != 0. {
+                            true => [p.x(), p.y(), 2.],
+                            false => [0., 0., 0.],
+                        })
+                        .collect()
+                });
+            self.annotations.push(CocoAnnotation {
+                id: self.annotations.len() as u64 + 1,
+                image_id,
+                category_id: bbox.id() + 1,
+                bbox: [bbox.xmin(), bbox.ymin(), bbox.width(), bbox.height()],
+                area,
+                iscrowd: 0,
+                score: results.then(|| bbox.confidence()),
+                segmentation,
+                num_keypoints: keypoints
+                    .as_ref()
+                    .map(|kpts| kpts.iter().skip(2).step_by(3).filter(|v| **v > 0.).count()),
+                keypoints,
+            });
+        }
+    }
+
+    /*
+    One line per instance, coordinates normalized by the image size:
+    `class cx cy w h` for boxes, `class cx cy w h px py v ...` with keypoints,
+    `class x1 y1 x2 y2 ...` with the largest polygon of a segmentation mask,
+    and `class x1 y1 ... x4 y4` with the corners of oriented boxes.
+    */
+    fn yolo_labels(&self, y: &YOLOResult, width: f32, height: f32) -> String {
+        let mut lines = vec![];
+        let polygons = match self.masks {
+            MaskEncoding::None => None,
+            _ => y.masks_polygons(),
+        };
+        let point = |p: &Point2| format!("{:.6} {:.6}", p.x() / width, p.y() / height);
+        for (i, bbox) in y.bboxes().into_iter().flatten().enumerate() {
+            let mut line = bbox.id().to_string();
+            // Ultralytics drops every segment of a dataset that mixes them with boxes, so in a
+            // segmentation export an instance without a usable polygon gets its box as one
+            let polygon = polygons.as_ref().map(|polygons| {
+                polygons
+                    .get(i)
+                    .and_then(|polygons| polygons.iter().max_by_key(|p| p.len()))
+                    .filter(|p| p.len() >= 3)
+                    .cloned()
+                    .unwrap_or_else(|| {
+                        let (x1, y1, x2, y2) = (bbox.xmin(), bbox.ymin(), bbox.xmax(), bbox.ymax());
+                        vec![
+                            Point2::new(x1, y1),
+                            Point2::new(x2, y1),
+                            Point2::new(x2, y2),
+                            Point2::new(x1, y2),
+                        ]
+                    })
+            });
+            match polygon {
+                Some(polygon) => {
+                    for p in polygon.iter() {
+                        line.push(' ');
+                        line.push_str(&point(p));
+                    }
+                }
+                None => {
+                    let cxcy = bbox.cxcy();
+                    line.push_str(&format!(
+                        " {} {:.6} {:.6}",
+                        point(&cxcy),
+                        bbox.width() / width,
+                        bbox.height() / height
+                    ));
+                    if let Some(kpts) = y.keypoints().and_then(|kpts| kpts.get(i)) {
+                        for p in kpts.iter() {
+                            let visible = p.x() != 0. || p.y() != 0.;
+                            line.push_str(&format!(
+                                " {} {}",
+                                point(p),
+                                if visible { 2 } else { 0 }
+                            ));
+                        }
+                    }
+                }
+            }
+            lines.push(line);
+        }
+        for obb in y.obbs().into_iter().flatten() {
+            let mut line = obb.id().to_string();
+            for p in obb.vertices().iter() {
+                line.push(' ');
+                line.push_str(&point(p));
+            }
+            lines.push(line);
+        }
+        lines.into_iter().map(|line| line + "\n").collect()
+    }
+
+    fn voc_annotation(&self, file_name: &str, y: &YOLOResult, width: u32, height: u32) -> String {
+        // oriented boxes are written as the boxes around them
+        let bboxes: Vec<Bbox> = y
+            .bboxes()
+            .into_iter()
+            .flatten()
+            .cloned()
+            .chain(y.obbs().into_iter().flatten().map(|obb| obb.bbox()))
+            .collect();
+        let mut xml = format!(
+            "<annotation>\n\t<folder>{IMAGES_DIR}</folder>\n\t<filename>{}</filename>\n\
+             \t<size>\n\t\t<width>{width}</width>\n\t\t<height>{height}</height>\n\
+             \t\t<depth>3</depth>\n\t</size>\n\t<segmented>0</segmented>\n",
+            xml_escape(file_name)
+        );
+        for bbox in bboxes.iter() {
+            // 1-based pixel coordinates, clipped to the image
+            let xmin = (bbox.xmin().round() as u32 + 1).clamp(1, width);
+            let ymin = (bbox.ymin().round() as u32 + 1).clamp(1, height);
+            let xmax = (bbox.xmax().round() as u32).clamp(xmin, width);
+            let ymax = (bbox.ymax().round() as u32).clamp(ymin, height);
+            let truncated = bbox.xmin() <= 0.
+                || bbox.ymin() <= 0.
+                || bbox.xmax() >= width as f32
+                || bbox.ymax() >= height as f32;
+            xml.push_str(&format!(
+                "\t<object>\n\t\t<name>{}</name>\n\t\t<pose>Unspecified</pose>\n\
+                 \t\t<truncated>{}</truncated>\n\t\t<difficult>0</difficult>\n\
+                 \t\t<bndbox>\n\t\t\t<xmin>{xmin}</xmin>\n\t\t\t<ymin>{ymin}</ymin>\n\
+                 \t\t\t<xmax>{xmax}</xmax>\n\t\t\t<ymax>{ymax}</ymax>\n\t\t</bndbox>\n\
+                 \t</object>\n",
+                xml_escape(&self.class_name(bbox.id())),
+                truncated as u8
+            ));
+        }
+        xml.push_str("</annotation>\n");
+        xml
+    }
+
+    fn class_name(&self, id: usize) -> String {
+        self.names
+            .get(id)
+            .cloned()
+            .unwrap_or_else(|| id.to_string())
+    }
+
+    fn data_yaml(&self) -> Result<String> {
+        // an absolute `path`, a relative one would resolve against the trainer's working
+        // directory. Names as JSON strings, which YAML reads as double-quoted scalars.
+        let mut yaml = format!(
+            "path: {}\ntrain: {IMAGES_DIR}\nval: {IMAGES_DIR}\n",
+            serde_json::to_string(&std::fs::canonicalize(&self.dir)?.display().to_string())?
+        );
+        if let Some(kpts) = self.kpts.filter(|_| self.format == ExportFormat::Yolo) {
+            yaml.push_str(&format!("kpt_shape: [{kpts}, 3]\n"));
+        }
+        yaml.push_str("names:\n");
+        for (i, name) in self.names.iter().enumerate() {
+            yaml.push_str(&format!("  {i}: {}\n", serde_json::to_string(name)?));
+        }
+        Ok(yaml)
+    }
+
+    // Writes the label files, returns the path of the main one.
+    pub fn finish(self) -> Result<PathBuf> {
+        let path = match self.format {
+            ExportFormat::CocoResults => {
+                let path = self.dir.join(COCO_RESULTS);
+                std::fs::write(&path, serde_json::to_string(&self.annotations)?)?;
+                path
+            }
+            ExportFormat::CocoAnnotations => {
+                let path = self.dir.join(COCO_ANNOTATIONS);
+                let dataset = CocoDataset {
+                    images: &self.images,
+                    annotations: &self.annotations,
+                    categories: self.categories(),
+                };
+                std::fs::write(&path, serde_json::to_string(&dataset)?)?;
+                path
+            }
+            ExportFormat::Yolo | ExportFormat::Voc => {
+                let path = self.dir.join(DATA_YAML);
+                std::fs::write(&path, self.data_yaml()?)?;
+                path
+            }
+        };
+        // the image ids of a results list, which `COCO.loadRes` has to find in the ground truth
+        if self.format == ExportFormat::CocoResults {
+            std::fs::write(
+                self.dir.join(COCO_IMAGES),
+                serde_json::to_string(&self.images)?,
+            )?;
+        }
+        Ok(path)
+    }
+
+    fn categories(&self) -> Vec<CocoCategory> {
+        self.names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| CocoCategory {
+                id: i + 1,
+                name: name.clone(),
+                supercategory: String::new(),
+            })
+            .collect()
+    }
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+// Frames of a folder of images, sorted by name, or of a video, named by their index.
+// They are decoded as they are taken, so a long video is never held in memory at once.
+pub fn read_source(
+    source: &str,
+    max_frames: usize,
+) -> Result<Box<dyn Iterator<Item = Result<(String, DynamicImage)>>>> {
+    let path = Path::new(source);
+    if !path.exists() {
+        bail!("No such image folder or video: {source}");
+    }
+    if !path.is_dir() {
+        let frames = VideoFrames::open(source, max_frames)?;
+        return Ok(Box::new(
+            frames
+                .enumerate()
+                .map(|(i, img)| Ok((format!("frame_{:06}.png", i + 1), img?))),
+        ));
+    }
+    let mut files = vec![];
+    for entry in std::fs::read_dir(path)? {
+        let path = entry?.path();
+        let is_image = path.extension().is_some_and(|ext| {
+            IMAGE_EXTENSIONS.contains(&ext.to_string_lossy().to_lowercase().as_str())
+        });
+        if is_image {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(Box::new(files.into_iter().map(|f| {
+        let name = f.file_name().unwrap().to_string_lossy().to_string();
+        Ok((name, image::open(f)?))
+    })))
+}
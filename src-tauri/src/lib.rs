@@ -5,14 +5,33 @@ use std::io::{Read, Write};
 
 pub mod app_backend;
 pub mod args;
+pub mod ensemble;
+pub mod export;
+pub mod mask;
+pub mod metadata;
 pub mod model;
 pub mod multi_capture;
+pub mod onnx;
 pub mod ort_backend;
+pub mod record;
+pub mod registry;
+pub mod self_test;
 pub mod yolo_result;
 pub use crate::args::Args;
-pub use crate::model::YOLOv8;
-pub use crate::ort_backend::{Batch, OrtBackend, OrtConfig, OrtEP, YOLOTask};
-pub use crate::yolo_result::{Bbox, Embedding, Point2, YOLOResult};
+pub use crate::ensemble::{Ensemble, EnsembleMember, EnsembleMode};
+pub use crate::export::{ExportFormat, Exporter};
+pub use crate::mask::{Mask, Masks, Rle};
+pub use crate::model::{RunTimes, Thresholds, YOLOv8};
+pub use crate::ort_backend::{
+    Batch, CpuOptions, EndToEnd, ModelFamily, OptLevel, OrtBackend, OrtConfig, OrtEP, PoolOptions,
+    QuantParams, YOLOTask,
+};
+pub use crate::record::{
+    ClassRecord, DetectionRecord, MaskEncoding, MaskRecord, ObbRecord, ResultRecord,
+};
+pub use crate::registry::{Manifest, ModelRecord};
+pub use crate::self_test::SelfTestReport;
+pub use crate::yolo_result::{Bbox, Embedding, Obb, Point2, YOLOResult};
 
 pub fn non_max_suppression(
     xs: &mut Vec<(Bbox, Option<Vec<Point2>>, Option<Vec<f32>>)>,
@@ -38,6 +57,103 @@ pub fn non_max_suppression(
     xs.truncate(current_index);
 }
 
+pub fn non_max_suppression_obb(xs: &mut Vec<Obb>, iou_threshold: f32) {
+    // same greedy suppression as above, using rotated IoU
+    xs.sort_by(|b1, b2| b2.confidence().partial_cmp(&b1.confidence()).unwrap());
+
+    let mut current_index = 0;
+    for index in 0..xs.len() {
+        let mut drop = false;
+        for prev_index in 0..current_index {
+            let iou = xs[prev_index].iou(&xs[index]);
+            if iou > iou_threshold {
+                drop = true;
+                break;
+            }
+        }
+        if !drop {
+            xs.swap(current_index, index);
+            current_index += 1;
+        }
+    }
+    xs.truncate(current_index);
+}
+
+pub fn weighted_boxes_fusion(xs: &[Vec<Bbox>], weights: &[f32], iou_threshold: f32) -> Vec<Bbox> {
+    // Weighted boxes fusion (Solovyev et al.): overlapping boxes of the same class from
+    // different models are averaged, weighted by confidence, instead of suppressed.
+    // `xs` holds the boxes of each model, `weights` the weight of each model.
+    let mut boxes: Vec<(&Bbox, f32, usize)> = xs
+        .iter()
+        .enumerate()
+        .flat_map(|(m, bboxes)| {
+            bboxes
+                .iter()
+                .map(move |b| (b, b.confidence() * weights[m], m))
+        })
+        .collect();
+    boxes.sort_by(|b1, b2| b2.1.partial_cmp(&b1.1).unwrap());
+
+    // clusters of (fused box, members as (box, weighted confidence, model))
+    let mut clusters: Vec<(Bbox, Vec<(&Bbox, f32, usize)>)> = Vec::new();
+    for (bbox, conf, m) in boxes.into_iter() {
+        match clusters
+            .iter_mut()
+            .find(|(fused, _)| fused.id() == bbox.id() && fused.iou(bbox) > iou_threshold)
+        {
+            Some((fused, members)) => {
+                members.push((bbox, conf, m));
+                let total: f32 = members.iter().map(|(_, c, _)| c).sum();
+                let avg = |f: fn(&Bbox) -> f32| {
+                    members.iter().map(|(b, c, _)| f(b) * c).sum::<f32>() / total
+                };
+                // the mean confidence weighted by model, so it stays within [0, 1]
+                let weight: f32 = members.iter().map(|(_, _, m)| weights[*m]).sum();
+                *fused = Bbox::new(
+                    avg(Bbox::xmin),
+                    avg(Bbox::ymin),
+                    avg(Bbox::width),
+                    avg(Bbox::height),
+                    bbox.id(),
+                    total / weight,
+                );
+            }
+            None => {
+                let fused = Bbox::new(
+                    bbox.xmin(),
+                    bbox.ymin(),
+                    bbox.width(),
+                    bbox.height(),
+                    bbox.id(),
+                    bbox.confidence(),
+                );
+                clusters.push((fused, vec![(bbox, conf, m)]));
+            }
+        }
+    }
+
+    // boxes found by fewer models lose confidence, several boxes of one model count once
+    let n_models = xs.len();
+    clusters
+        .into_iter()
+        .map(|(fused, members)| {
+            let mut models: Vec<usize> = members.iter().map(|(_, _, m)| *m).collect();
+            models.sort();
+            models.dedup();
+            let confidence =
+                fused.confidence() * models.len().min(n_models) as f32 / n_models as f32;
+            Bbox::new(
+                fused.xmin(),
+                fused.ymin(),
+                fused.width(),
+                fused.height(),
+                fused.id(),
+                confidence,
+            )
+        })
+        .collect()
+}
+
 pub fn gen_time_string(delimiter: &str) -> String {
     let offset = chrono::FixedOffset::east_opt(8 * 60 * 60).unwrap(); // Beijing
     let t_now = chrono::Utc::now().with_timezone(&offset);
@@ -109,6 +225,91 @@ pub enum LoadError {
 
     #[error("Failed to load model: {0}")]
     Model(#[from] ort::OrtError),
+
+    #[error("Invalid model args: {0}. Check the field names and types in the args toml.")]
+    Args(Box<figment::Error>),
+
+    #[error("Model file `{0}` not found. Check `model` in the args toml.")]
+    NotFound(String),
+
+    #[error("Failed to read model `{0}`: {1}")]
+    Read(String, std::io::Error),
+
+    #[error("Invalid ONNX model: {0}. Check that `model` is an ONNX file.")]
+    Onnx(String),
+
+    #[error(
+        "Invalid labels file `{0}`: {1}. Use an Ultralytics data.yaml or a text file with one \
+         class name per line."
+    )]
+    Labels(String, String),
+
+    #[error("Invalid model manifest `{0}`: {1}")]
+    Manifest(String, String),
+
+    #[error("Model `{0}` is not registered. Add it to the manifest in `models_dir`.")]
+    Unregistered(String),
+
+    #[error(
+        "SHA-256 of `{file}` is {actual}, the manifest has {expected}. The file was changed or \
+         corrupted, check it before updating the manifest."
+    )]
+    Integrity {
+        file: String,
+        expected: String,
+        actual: String,
+    },
+
+    #[error(
+        "The model has a fixed batch size of {expected}, got {got}. Set `batch = {expected}` in \
+         the args toml or export the model with a dynamic batch."
+    )]
+    Batch { expected: u32, got: u32 },
+
+    #[error("Failed to get the model input {0}, it is dynamic. Set `{0}` in the args toml.")]
+    InputSize(&'static str),
+
+    #[error(
+        "Failed to get the task: {0}. Set `task` in the args toml to classify, detect, pose, \
+         segment or obb."
+    )]
+    Task(String),
+
+    #[error("Failed to get the number of {name}. Set `{key}` in the args toml.")]
+    Count {
+        name: &'static str,
+        key: &'static str,
+    },
+
+    #[error(
+        "Input dtype mismatch, expected Float32, got {0}. Set `fp16 = true` in the args toml."
+    )]
+    Fp16(String),
+
+    #[error(
+        "Unsupported tensor dtype {0}. Export the model with Float32, Float16, Uint8 or Int8 \
+         inputs and float or integer outputs."
+    )]
+    Dtype(String),
+
+    #[error(
+        "Invalid thresholds: {0}. Check conf, iou, kconf, class_conf and hidden_classes in the \
+         args toml."
+    )]
+    Thresholds(anyhow::Error),
+}
+
+impl From<figment::Error> for LoadError {
+    fn from(e: figment::Error) -> Self {
+        Self::Args(Box::new(e))
+    }
+}
+
+// tauri commands return their errors to the frontend as the message
+impl serde::Serialize for LoadError {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
 }
 
 pub fn run() {
@@ -123,6 +324,7 @@ pub fn run() {
     }
 
     tauri::Builder::default()
+        .manage(app_backend::ModelState::default())
         .setup(|_app| {
             log::info!("{}", chrono::Local::now().format("%Y-%m-%d %H:%M:%S"));
             log::info!("Tauri application started.");
@@ -131,6 +333,17 @@ pub fn run() {
         .invoke_handler(tauri::generate_handler![
             app_backend::poll_and_emit_image_sources,
             app_backend::start_streaming,
+            app_backend::list_models,
+            app_backend::load_model,
+            app_backend::activate_model,
+            app_backend::get_self_test,
+            app_backend::get_client_overlay,
+            app_backend::set_client_overlay,
+            app_backend::start_export,
+            app_backend::stop_export,
+            app_backend::get_thresholds,
+            app_backend::get_class_names,
+            app_backend::set_thresholds,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
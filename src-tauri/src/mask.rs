@@ -0,0 +1,411 @@
+use image::GrayImage;
+use imageproc::contours::{find_contours, BorderType};
+use imageproc::geometry::approximate_polygon_dp;
+use ndarray::{s, Array, Array2, ArrayView2, IxDyn};
+use std::sync::{Arc, OnceLock};
+
+use crate::{Bbox, Point2};
+
+// max distance (px) between a contour and its simplified polygon
+const POLYGON_EPSILON: f64 = 1.0;
+
+#[derive(Debug, Clone, PartialEq, Default, serde::Serialize, serde::Deserialize)]
+pub struct Rle {
+    // COCO-style uncompressed run-length encoding of a binary mask.
+    // Pixels are read column by column and `counts` alternates between
+    // runs of background and foreground, always starting with background.
+    size: [u32; 2], // [height, width]
+    counts: Vec<u32>,
+}
+
+impl Rle {
+    pub fn encode(mask: &[u8], width: u32, height: u32) -> Self {
+        // any non-zero pixel is foreground
+        let mut runs = Runs::default();
+        for x in 0..width as usize {
+            for y in 0..height as usize {
+                runs.push(mask[y * width as usize + x] > 0, 1);
+            }
+        }
+        runs.finish(width, height)
+    }
+
+    pub fn decode(&self) -> Vec<u8> {
+        // back to a row-major mask with 255 as foreground
+        let (height, width) = (self.height() as usize, self.width() as usize);
+        let mut mask = vec![0u8; height * width];
+        let mut i = 0usize;
+        for (n, &run) in self.counts.iter().enumerate() {
+            if n % 2 == 1 {
+                for j in i..i + run as usize {
+                    mask[(j % height) * width + j / height] = 255;
+                }
+            }
+            i += run as usize;
+        }
+        mask
+    }
+
+    pub fn height(&self) -> u32 {
+        self.size[0]
+    }
+
+    pub fn width(&self) -> u32 {
+        self.size[1]
+    }
+
+    pub fn counts(&self) -> &Vec<u32> {
+        &self.counts
+    }
+
+    pub fn area(&self) -> u32 {
+        self.counts.iter().skip(1).step_by(2).sum()
+    }
+
+    pub fn compressed(&self) -> String {
+        // the string form of `counts` used by pycocotools (`rleToString` in maskApi.c):
+        // from the third run on each count is stored as the difference to the one two before,
+        // as 5-bit chunks, low first, with 0x20 marking that more follow, offset by 48
+        let mut s = String::new();
+        for (i, &count) in self.counts.iter().enumerate() {
+            let mut x = count as i64;
+            if i > 2 {
+                x -= self.counts[i - 2] as i64;
+            }
+            loop {
+                let mut c = x & 0x1f;
+                x >>= 5;
+                let more = if c & 0x10 != 0 { x != -1 } else { x != 0 };
+                if more {
+                    c |= 0x20;
+                }
+                s.push((c as u8 + 48) as char);
+                if !more {
+                    break;
+                }
+            }
+        }
+        s
+    }
+}
+
+#[derive(Default)]
+struct Runs {
+    // column-major runs as they are read, starting with background
+    counts: Vec<u32>,
+    current: bool,
+    run: u32,
+}
+
+impl Runs {
+    fn push(&mut self, value: bool, n: u32) {
+        if n == 0 {
+            return;
+        }
+        if value != self.current {
+            self.counts.push(self.run);
+            self.current = value;
+            self.run = 0;
+        }
+        self.run += n;
+    }
+
+    fn finish(mut self, width: u32, height: u32) -> Rle {
+        self.counts.push(self.run);
+        Rle {
+            size: [height, width],
+            counts: self.counts,
+        }
+    }
+}
+
+pub fn polygons(mask: &[u8], width: u32, height: u32) -> Vec<Vec<Point2>> {
+    // outer contours of every foreground region, simplified with Douglas-Peucker.
+    // Regions touching the border are not traced, so the mask gets a 1px empty frame.
+    assert_eq!(mask.len(), (width * height) as usize, "valid mask size");
+    if width == 0 || height == 0 {
+        return vec![];
+    }
+    let stride = width as usize + 2;
+    let mut padded = vec![0u8; stride * (height as usize + 2)];
+    for (y, row) in mask.chunks_exact(width as usize).enumerate() {
+        let start = (y + 1) * stride + 1;
+        padded[start..start + row.len()].copy_from_slice(row);
+    }
+    let img = GrayImage::from_raw(width + 2, height + 2, padded).expect("valid padded size");
+    find_contours::<i32>(&img)
+        .into_iter()
+        .filter(|contour| contour.border_type == BorderType::Outer)
+        .map(|contour| approximate_polygon_dp(&contour.points, POLYGON_EPSILON, true))
+        .filter(|points| points.len() >= 3)
+        .map(|points| {
+            points
+                .iter()
+                .map(|p| Point2::new((p.x - 1) as f32, (p.y - 1) as f32))
+                .collect()
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Mask {
+    // binary mask (0 or 255) of one detection, cropped to its bbox in the original image
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    data: Vec<u8>,
+}
+
+impl Mask {
+    pub fn x(&self) -> u32 {
+        self.x
+    }
+
+    pub fn y(&self) -> u32 {
+        self.y
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn data(&self) -> &Vec<u8> {
+        &self.data
+    }
+
+    pub fn area(&self) -> u32 {
+        self.data.iter().filter(|&&v| v > 0).count() as u32
+    }
+
+    pub fn to_full(&self, width: u32, height: u32) -> Vec<u8> {
+        // paste the crop into an empty mask of the given image size
+        let mut full = vec![0u8; (width * height) as usize];
+        for y in 0..self.height.min(height.saturating_sub(self.y)) {
+            for x in 0..self.width.min(width.saturating_sub(self.x)) {
+                full[((self.y + y) * width + self.x + x) as usize] =
+                    self.data[(y * self.width + x) as usize];
+            }
+        }
+        full
+    }
+
+    pub fn rle(&self, width: u32, height: u32) -> Rle {
+        // column-major runs of the crop, the background around it is counted, not scanned
+        let (x0, y0) = (self.x.min(width), self.y.min(height));
+        let (w, h) = (self.width.min(width - x0), self.height.min(height - y0));
+        let mut runs = Runs::default();
+        runs.push(false, x0 * height);
+        for x in 0..w {
+            runs.push(false, y0);
+            for y in 0..h {
+                runs.push(self.data[(y * self.width + x) as usize] > 0, 1);
+            }
+            runs.push(false, height - y0 - h);
+        }
+        runs.push(false, (width - x0 - w) * height);
+        runs.finish(width, height)
+    }
+
+    pub fn polygons(&self) -> Vec<Vec<Point2>> {
+        // contours of the crop, moved back to image coordinates
+        polygons(&self.data, self.width, self.height)
+            .into_iter()
+            .map(|polygon| {
+                polygon
+                    .iter()
+                    .map(|p| Point2::new(p.x() + self.x as f32, p.y() + self.y as f32))
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, PartialEq, Default)]
+pub struct Masks {
+    // Undecoded masks of all detections in one image: a row of mask coefficients
+    // per detection, plus the prototype output of the whole batch, shared by every
+    // result of that batch. Decoding happens once, on first access.
+    protos: Arc<Array<f32, IxDyn>>,
+    index: usize,
+    coefs: Array2<f32>,
+    bboxes: Vec<Bbox>,
+    width: u32,
+    height: u32,
+    decoded: OnceLock<Vec<Mask>>,
+}
+
+impl std::fmt::Debug for Masks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Masks")
+            .field("n", &self.len())
+            .field("decoded", &self.decoded.get().is_some())
+            .finish()
+    }
+}
+
+impl Masks {
+    pub fn new(
+        protos: Arc<Array<f32, IxDyn>>,
+        index: usize,
+        coefs: Array2<f32>,
+        bboxes: Vec<Bbox>,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        Self {
+            protos,
+            index,
+            coefs,
+            bboxes,
+            width,
+            height,
+            decoded: OnceLock::new(),
+        }
+    }
+
+    // Masks read back from their run-length encodings, already decoded.
+    pub fn from_rle(bboxes: Vec<Bbox>, rles: &[Rle]) -> Self {
+        let (width, height) = rles
+            .first()
+            .map_or((0, 0), |rle| (rle.width(), rle.height()));
+        let decoded: Vec<Mask> = rles
+            .iter()
+            .zip(bboxes.iter())
+            .map(|(rle, bbox)| {
+                let full = rle.decode();
+                let (x0, y0, x1, y1) = crop_bounds(bbox, width, height);
+                let mut data = Vec::with_capacity(((x1 - x0) * (y1 - y0)) as usize);
+                for y in y0..y1 {
+                    let row = (y * width) as usize;
+                    data.extend_from_slice(&full[row + x0 as usize..row + x1 as usize]);
+                }
+                Mask {
+                    x: x0,
+                    y: y0,
+                    width: x1 - x0,
+                    height: y1 - y0,
+                    data,
+                }
+            })
+            .collect();
+        Self {
+            coefs: Array2::zeros((bboxes.len(), 0)),
+            bboxes,
+            width,
+            height,
+            decoded: OnceLock::from(decoded),
+            ..Default::default()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.bboxes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bboxes.is_empty()
+    }
+
+    pub fn coefs(&self) -> &Array2<f32> {
+        &self.coefs
+    }
+
+    pub fn protos(&self) -> &Arc<Array<f32, IxDyn>> {
+        &self.protos
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn decode(&self) -> &Vec<Mask> {
+        self.decoded.get_or_init(|| self.decode_all())
+    }
+
+    pub fn rle(&self) -> Vec<Rle> {
+        self.decode()
+            .iter()
+            .map(|mask| mask.rle(self.width, self.height))
+            .collect()
+    }
+
+    pub fn polygons(&self) -> Vec<Vec<Vec<Point2>>> {
+        self.decode().iter().map(|mask| mask.polygons()).collect()
+    }
+
+    fn decode_all(&self) -> Vec<Mask> {
+        if self.is_empty() {
+            return vec![];
+        }
+
+        // one matmul for every detection: (n, nm) x (nm, mh * mw)
+        let proto = self.protos.slice(s![self.index, .., .., ..]);
+        let (nm, mh, mw) = proto.dim();
+        let proto = proto.into_shape((nm, mh * mw)).expect("contiguous proto");
+        let logits = self.coefs.dot(&proto);
+
+        // original image -> proto scale, letterbox padding is at the bottom right
+        let scale = (mw as f32 / self.width as f32).min(mh as f32 / self.height as f32);
+        logits
+            .outer_iter()
+            .zip(self.bboxes.iter())
+            .map(|(logits, bbox)| {
+                let logits = logits.into_shape((mh, mw)).expect("valid mask shape");
+                self.decode_crop(logits, bbox, scale)
+            })
+            .collect()
+    }
+
+    fn decode_crop(&self, logits: ArrayView2<f32>, bbox: &Bbox, scale: f32) -> Mask {
+        // bilinear upsampling of the logits inside the bbox only; logit > 0 is foreground
+        let (mh, mw) = logits.dim();
+        let (x0, y0, x1, y1) = crop_bounds(bbox, self.width, self.height);
+        let (width, height) = (x1 - x0, y1 - y0);
+
+        // sample position of pixel `p` along an axis of `n` proto cells
+        let sample = |p: u32, n: usize| {
+            let v = ((p as f32 + 0.5) * scale - 0.5).clamp(0., (n - 1) as f32);
+            let i = v.floor() as usize;
+            (i, (i + 1).min(n - 1), v - i as f32)
+        };
+        let xs: Vec<_> = (x0..x1).map(|x| sample(x, mw)).collect();
+
+        let mut data = vec![0u8; (width * height) as usize];
+        for (j, y) in (y0..y1).enumerate() {
+            let (ya, yb, fy) = sample(y, mh);
+            for (i, &(xa, xb, fx)) in xs.iter().enumerate() {
+                let top = logits[[ya, xa]] * (1. - fx) + logits[[ya, xb]] * fx;
+                let bottom = logits[[yb, xa]] * (1. - fx) + logits[[yb, xb]] * fx;
+                if top * (1. - fy) + bottom * fy > 0. {
+                    data[j * width as usize + i] = 255;
+                }
+            }
+        }
+
+        Mask {
+            x: x0,
+            y: y0,
+            width,
+            height,
+            data,
+        }
+    }
+}
+
+fn crop_bounds(bbox: &Bbox, width: u32, height: u32) -> (u32, u32, u32, u32) {
+    // pixels covered by a bbox, clipped to the image
+    let x1 = (bbox.xmax().max(0.).ceil() as u32).min(width);
+    let y1 = (bbox.ymax().max(0.).ceil() as u32).min(height);
+    let x0 = (bbox.xmin().max(0.).floor() as u32).min(x1);
+    let y0 = (bbox.ymin().max(0.).floor() as u32).min(y1);
+    (x0, y0, x1, y1)
+}
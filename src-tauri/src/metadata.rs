@@ -0,0 +1,135 @@
+use std::path::Path;
+
+use yaml_rust::{Yaml, YamlLoader};
+
+use crate::LoadError;
+
+/*
+Parses a metadata value of an Ultralytics export. They are written with
+Python's `str()`, e.g. `{0: 'person', 27: "yellow_lady's_slipper"}` or
+`[17, 3]`, which are YAML flow nodes apart from the string quoting, so the
+Python strings are rewritten as YAML double-quoted ones first.
+*/
+pub fn parse(value: &str) -> Option<Yaml> {
+    YamlLoader::load_from_str(&python_to_yaml(value))
+        .ok()?
+        .into_iter()
+        .next()
+}
+
+fn python_to_yaml(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\'' && c != '"' {
+            out.push(c);
+            continue;
+        }
+
+        // `\'` is the only Python escape YAML does not know, `\\`, `\n`, `\x..`, `\u....` are the same
+        let quote = c;
+        out.push('"');
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some('\'') => out.push('\''),
+                    Some(e) => {
+                        out.push('\\');
+                        out.push(e);
+                    }
+                    None => break,
+                },
+                c if c == quote => break,
+                '"' => out.push_str("\\\""),
+                c => out.push(c),
+            }
+        }
+        out.push('"');
+    }
+    out
+}
+
+fn scalar(x: &Yaml) -> Option<String> {
+    match x {
+        Yaml::String(s) | Yaml::Real(s) => Some(s.clone()),
+        Yaml::Integer(i) => Some(i.to_string()),
+        Yaml::Boolean(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+pub fn names(yaml: &Yaml) -> Option<Vec<String>> {
+    // class names, either `{0: person, 1: bicycle, ...}` or `[person, bicycle, ...]`
+    match yaml {
+        Yaml::Array(xs) => xs.iter().map(scalar).collect(),
+        Yaml::Hash(map) => {
+            let mut names: Vec<String> = (0..map.len()).map(|i| i.to_string()).collect();
+            for (k, v) in map.iter() {
+                let i = k.as_i64()? as usize;
+                *names.get_mut(i)? = scalar(v)?;
+            }
+            Some(names)
+        }
+        _ => None,
+    }
+}
+
+pub fn kpt_shape(yaml: &Yaml) -> Option<(u32, u32)> {
+    // `[17, 3]`: number of keypoints and values per keypoint
+    let shape = yaml.as_vec()?;
+    Some((
+        shape.first()?.as_i64()? as u32,
+        shape.get(1)?.as_i64()? as u32,
+    ))
+}
+
+pub fn skeleton(yaml: &Yaml) -> Option<Vec<(usize, usize)>> {
+    // `[[15, 13], [13, 11], ...]`
+    yaml.as_vec()?
+        .iter()
+        .map(|edge| {
+            let edge = edge.as_vec()?;
+            Some((
+                edge.first()?.as_i64()? as usize,
+                edge.get(1)?.as_i64()? as usize,
+            ))
+        })
+        .collect()
+}
+
+/*
+Reads class names from an Ultralytics `data.yaml` (`names` as a list or
+mapping, with an optional `nc`) or from a text file with one name per line.
+*/
+pub fn load_labels(path: &Path) -> Result<Vec<String>, LoadError> {
+    let file = path.display().to_string();
+    let content = std::fs::read_to_string(path).map_err(|e| LoadError::Read(file.clone(), e))?;
+    let is_yaml = path
+        .extension()
+        .is_some_and(|ext| ext == "yaml" || ext == "yml");
+    if !is_yaml {
+        return Ok(content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(String::from)
+            .collect());
+    }
+
+    let doc = YamlLoader::load_from_str(&content)
+        .map_err(|e| LoadError::Labels(file.clone(), e.to_string()))?
+        .into_iter()
+        .next()
+        .unwrap_or(Yaml::Null);
+    let names = names(&doc["names"])
+        .ok_or_else(|| LoadError::Labels(file.clone(), "no `names` list or mapping".to_string()))?;
+    if let Some(nc) = doc["nc"].as_i64() {
+        if nc as usize != names.len() {
+            return Err(LoadError::Labels(
+                file,
+                format!("`nc` is {nc} but there are {} names", names.len()),
+            ));
+        }
+    }
+    Ok(names)
+}
@@ -1,22 +1,123 @@
 #![allow(clippy::type_complexity)]
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use image::{DynamicImage, GenericImageView, ImageBuffer};
-use log::info;
+use log::{info, warn};
 use ndarray::parallel::prelude::*;
-use ndarray::{s, Array, Axis, IxDyn};
+use ndarray::{s, Array, ArrayView, Axis, IxDyn};
 use rand::{thread_rng, Rng};
-use std::path::PathBuf;
-use std::time::Instant;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use crate::self_test::{self_test, SelfTestReport};
 use crate::{
-    check_font, gen_time_string, multi_capture, non_max_suppression, Args, Batch, Bbox, Embedding,
-    OrtBackend, OrtConfig, OrtEP, Point2, YOLOResult, YOLOTask,
+    check_font, gen_time_string, metadata, non_max_suppression, non_max_suppression_obb, Args,
+    Batch, Bbox, Embedding, LoadError, Manifest, Masks, ModelFamily, Obb, OrtBackend, OrtConfig,
+    OrtEP, Point2, YOLOResult, YOLOTask,
 };
 
+// timed batches per setting of the ORT auto-tune
+const AUTOTUNE_ITERATIONS: u32 = 10;
+
+// opacity of the segmentation mask overlay
+const MASK_ALPHA: f32 = 0.4;
+
+// default skeletons, used when neither the config nor the model metadata has one
+const COCO_SKELETON: [(usize, usize); 19] = [
+    (15, 13),
+    (13, 11),
+    (16, 14),
+    (14, 12),
+    (11, 12),
+    (5, 11),
+    (6, 12),
+    (5, 6),
+    (5, 7),
+    (6, 8),
+    (7, 9),
+    (8, 10),
+    (1, 2),
+    (0, 1),
+    (0, 2),
+    (1, 3),
+    (2, 4),
+    (3, 5),
+    (4, 6),
+];
+const HAND_SKELETON: [(usize, usize); 21] = [
+    (0, 1),
+    (1, 2),
+    (2, 3),
+    (3, 4),
+    (0, 5),
+    (5, 6),
+    (6, 7),
+    (7, 8),
+    (5, 9),
+    (9, 10),
+    (10, 11),
+    (11, 12),
+    (9, 13),
+    (13, 14),
+    (14, 15),
+    (15, 16),
+    (13, 17),
+    (0, 17),
+    (17, 18),
+    (18, 19),
+    (19, 20),
+];
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Thresholds {
+    // thresholds that can be changed while the model is running
+    pub conf: f32,
+    pub iou: f32,
+    pub kconf: f32,
+    pub class_conf: BTreeMap<String, f32>,
+    pub hidden_classes: Vec<String>,
+}
+
+impl Thresholds {
+    pub fn validate(&self, names: &[String]) -> Result<()> {
+        for (name, value) in [
+            ("conf", self.conf),
+            ("iou", self.iou),
+            ("kconf", self.kconf),
+        ]
+        .into_iter()
+        .chain(self.class_conf.iter().map(|(k, &v)| (k.as_str(), v)))
+        {
+            if !(0.0..=1.0).contains(&value) {
+                bail!("`{name}` must be between 0 and 1, got {value}");
+            }
+        }
+        for name in self.class_conf.keys().chain(self.hidden_classes.iter()) {
+            if !names.contains(name) {
+                bail!(
+                    "Unknown class `{name}`, the model has: {}",
+                    names.join(", ")
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, serde::Serialize)]
+pub struct RunTimes {
+    // stage durations of the last `run`
+    pub preprocess: Duration,
+    pub inference: Duration,
+    pub postprocess: Duration,
+}
+
 pub struct YOLOv8 {
     // YOLOv8 model for all yolo-tasks
     engine: OrtBackend,
+    model_id: Arc<str>,
     nc: u32,
     nk: u32,
     nm: u32,
@@ -27,14 +128,83 @@ pub struct YOLOv8 {
     conf: f32,
     kconf: f32,
     iou: f32,
+    class_conf: BTreeMap<String, f32>,
+    hidden_classes: Vec<String>,
+    class_thresholds: Vec<Option<f32>>,
+    topk: usize,
+    tta: Vec<(f32, bool)>,
+    input: Array<f32, IxDyn>,
+    outputs: Vec<Arc<Array<f32, IxDyn>>>,
+    times: RunTimes,
     names: Vec<String>,
     color_palette: Vec<(u8, u8, u8)>,
+    skeleton: Vec<(usize, usize)>,
+    kpt_palette: Vec<(u8, u8, u8)>,
     profile: bool,
     plot: bool,
+    self_test: Option<SelfTestReport>,
 }
 
 impl YOLOv8 {
-    pub fn new(config: Args) -> Result<Self> {
+    pub fn new(config: Args) -> Result<Self, LoadError> {
+        Self::build(config, None)
+    }
+
+    // Loads the model from its bytes instead of `config.model`, which then only names it.
+    pub fn new_from_memory(config: Args, model: Vec<u8>) -> Result<Self, LoadError> {
+        Self::build(config, Some(Arc::from(model)))
+    }
+
+    fn build(mut config: Args, bytes: Option<Arc<[u8]>>) -> Result<Self, LoadError> {
+        let thresholds = config.thresholds();
+
+        // a registered model is checked against its manifest entry, which also
+        // describes it better than args shared by several models
+        let (bytes, record) = match &config.model_id {
+            Some(id) => {
+                let dir = Path::new(&config.models_dir);
+                let record = Manifest::load(dir)?.get(id)?.clone();
+                let bytes = match bytes {
+                    Some(bytes) => {
+                        record.verify(&bytes, &record.path(dir).display().to_string())?;
+                        bytes
+                    }
+                    None => Arc::from(record.read(dir)?),
+                };
+                info!("Verified model {id}: {} {}", record.sha256(), record.file());
+                config.model = record.path(dir).to_string_lossy().to_string();
+                if let Some(task) = record.task() {
+                    config.task = Some(task.clone());
+                }
+                config.width = record.width().or(config.width);
+                config.height = record.height().or(config.height);
+                // the verified bytes are run, an optimized copy would bypass the checksum
+                if let Some(optimized) = config.optimized_model.take() {
+                    warn!(
+                        "Ignoring optimized_model {optimized} for registered model {id}, \
+                         the manifest only verifies {}.",
+                        record.file()
+                    );
+                }
+                (Some(bytes), Some(record))
+            }
+            None => (bytes, None),
+        };
+        let model_path = config.model.clone();
+        let model_id = match &record {
+            Some(record) => record.id().clone(),
+            None => {
+                warn!(
+                    "Model {model_path} is not registered, results are tagged with its file name."
+                );
+                Path::new(&model_path)
+                    .file_stem()
+                    .map_or(model_path.clone(), |stem| {
+                        stem.to_string_lossy().to_string()
+                    })
+            }
+        };
+
         // execution provider
         let ep = if config.trt {
             OrtEP::Trt(config.device_id)
@@ -52,14 +222,30 @@ impl YOLOv8 {
         };
 
         // build ort engine
-        let ort_args = OrtConfig {
+        let mut ort_args = OrtConfig {
             ep,
             batch,
+            cpu: config.cpu_options(),
+            pool: config.pool_options(),
+            output_quant: config.output_quant(),
             f: config.model,
+            bytes,
+            optimized: config.optimized_model,
             task: config.task,
+            family: config.family,
             trt_fp16: config.fp16,
+            normalized: config.normalized_input,
+            input_scale: config.input_scale,
+            input_zero_point: config.input_zero_point,
             image_size: (config.height, config.width),
         };
+        if config.ort_autotune {
+            if let OrtEP::Cpu = ort_args.ep {
+                ort_args.cpu = OrtBackend::autotune(&ort_args, AUTOTUNE_ITERATIONS)?;
+            } else {
+                warn!("ORT auto-tune only runs on the CPU execution provider, skipping it.");
+            }
+        }
         let engine = OrtBackend::build(ort_args)?;
 
         //  get batch, height, width, tasks, nc, nk, nm
@@ -69,27 +255,54 @@ impl YOLOv8 {
             engine.width(),
             engine.task(),
         );
-        let nc = engine.nc().or(config.nc).unwrap_or_else(|| {
-            panic!("Failed to get num_classes, make it explicit with `--nc`");
-        });
+        // class names: manifest first, then the labels file, then metadata
+        let labels = match (&record, &config.labels) {
+            (Some(record), _) if !record.names().is_empty() => {
+                Some((format!("Manifest entry {model_id}"), record.names().clone()))
+            }
+            (_, Some(path)) => Some((path.clone(), metadata::load_labels(Path::new(path))?)),
+            _ => None,
+        };
+        let nc = engine
+            .nc()
+            .or(config.nc)
+            .or(labels.as_ref().map(|(_, labels)| labels.len() as u32))
+            .ok_or(LoadError::Count {
+                name: "classes",
+                key: "nc",
+            })?;
         let (nk, nm) = match task {
             YOLOTask::Pose => {
-                let nk = engine.nk().or(config.nk).unwrap_or_else(|| {
-                    panic!("Failed to get num_keypoints, make it explicit with `--nk`");
-                });
+                let nk = engine.nk().or(config.nk).ok_or(LoadError::Count {
+                    name: "keypoints",
+                    key: "nk",
+                })?;
                 (nk, 0)
             }
             YOLOTask::Segment => {
-                let nm = engine.nm().or(config.nm).unwrap_or_else(|| {
-                    panic!("Failed to get num_masks, make it explicit with `--nm`");
-                });
+                let nm = engine.nm().or(config.nm).ok_or(LoadError::Count {
+                    name: "masks",
+                    key: "nm",
+                })?;
                 (0, nm)
             }
             _ => (0, 0),
         };
 
-        // class names
-        let names = engine.names().unwrap_or(vec!["Unknown".to_string()]);
+        let names = match labels {
+            Some((source, mut labels)) => {
+                if labels.len() != nc as usize {
+                    warn!(
+                        "{source} has {} class names, but the model has {nc} classes.",
+                        labels.len()
+                    );
+                    // every class id needs a name
+                    labels.extend((labels.len()..nc as usize).map(|i| i.to_string()));
+                }
+                labels
+            }
+            None => engine.names().unwrap_or(vec!["Unknown".to_string()]),
+        };
 
         // color palette
         let mut rng = thread_rng();
@@ -104,15 +317,98 @@ impl YOLOv8 {
             })
             .collect();
 
-        Ok(Self {
+        // skeleton: config first, then metadata, then the known keypoint layouts
+        let skeleton = match config.skeleton {
+            Some(edges) => edges.into_iter().map(|[a, b]| (a, b)).collect(),
+            None => engine.skeleton().unwrap_or_else(|| match nk {
+                17 => COCO_SKELETON.to_vec(),
+                21 => HAND_SKELETON.to_vec(),
+                _ => vec![],
+            }),
+        };
+        let skeleton: Vec<_> = skeleton
+            .into_iter()
+            .filter(|&(a, b)| {
+                let valid = a < nk as usize && b < nk as usize;
+                if !valid {
+                    warn!("Ignoring skeleton edge ({a}, {b}), the model has {nk} keypoints.");
+                }
+                valid
+            })
+            .collect();
+
+        // test-time augmentation: (scale, flip) of every copy of a frame in the batch
+        let tta = if !config.tta {
+            vec![]
+        } else if task != YOLOTask::Detect {
+            warn!("Test-time augmentation only supports detection, it is disabled.");
+            vec![]
+        } else if !engine.is_batch_dynamic() {
+            warn!("Test-time augmentation needs a dynamic batch size, it is disabled.");
+            vec![]
+        } else {
+            let mut scales: Vec<f32> = config
+                .tta_scales
+                .iter()
+                .copied()
+                .filter(|&scale| {
+                    let valid = scale > 0. && scale <= 1.;
+                    if !valid {
+                        warn!(
+                            "Ignoring test-time augmentation scale {scale}, it must be in (0, 1]."
+                        );
+                    }
+                    valid
+                })
+                .collect();
+            if scales.is_empty() {
+                scales.push(1.);
+            }
+            scales
+                .into_iter()
+                .flat_map(|scale| {
+                    let flips: &[bool] = if config.tta_flip {
+                        &[false, true]
+                    } else {
+                        &[false]
+                    };
+                    flips.iter().map(move |&flip| (scale, flip))
+                })
+                .collect()
+        };
+
+        // keypoint palette
+        let kpt_palette: Vec<_> = (0..nk)
+            .map(|_| {
+                (
+                    rng.gen_range(0..=255),
+                    rng.gen_range(0..=255),
+                    rng.gen_range(0..=255),
+                )
+            })
+            .collect();
+
+        let mut model = Self {
             engine,
+            model_id: Arc::from(model_id),
             names,
             conf: config.conf,
             kconf: config.kconf,
             iou: config.iou,
+            class_conf: BTreeMap::new(),
+            hidden_classes: vec![],
+            class_thresholds: vec![],
+            topk: config.topk,
+            tta,
+            input: Array::zeros(IxDyn(&[0])),
+            outputs: vec![],
+            times: RunTimes::default(),
             color_palette,
+            skeleton,
+            kpt_palette,
             profile: config.profile,
             plot: config.plot,
+            self_test: None,
             nc,
             nk,
             nm,
@@ -120,7 +416,20 @@ impl YOLOv8 {
             width,
             batch,
             task,
-        })
+        };
+        model
+            .set_thresholds(thresholds)
+            .map_err(LoadError::Thresholds)?;
+
+        // warm-up and self-test, so the first real batch does not pay for initialization
+        if config.warmup > 0 || config.self_test {
+            let images = config
+                .self_test
+                .then(|| PathBuf::from(&config.self_test_images));
+            let report = self_test(&model, &model_path, config.warmup, images.as_deref());
+            model.self_test = Some(report);
+        }
+        Ok(model)
     }
 
     pub fn scale_wh(&self, w0: f32, h0: f32, w1: f32, h1: f32) -> (f32, f32, f32) {
@@ -128,38 +437,74 @@ impl YOLOv8 {
         (r, (w0 * r).round(), (h0 * r).round())
     }
 
-    pub fn preprocess(&mut self, xs: &Vec<DynamicImage>) -> Result<Array<f32, IxDyn>> {
+    pub fn preprocess(&mut self, xs: &[DynamicImage]) -> Result<Array<f32, IxDyn>> {
+        self.preprocess_augmented(xs, &[(1., false)])
+    }
+
+    pub fn preprocess_augmented(
+        &self,
+        xs: &[DynamicImage],
+        augs: &[(f32, bool)],
+    ) -> Result<Array<f32, IxDyn>> {
+        let mut ys = Array::zeros(IxDyn(&[0]));
+        self.preprocess_into(xs, augs, &mut ys);
+        Ok(ys)
+    }
+
+    pub fn preprocess_into(
+        &self,
+        xs: &[DynamicImage],
+        augs: &[(f32, bool)],
+        ys: &mut Array<f32, IxDyn>,
+    ) {
+        // every image is letterboxed once per (scale, flip), next to each other in the batch;
+        // `ys` is reused as long as the shape stays the same
         let fill_val = 144.0 / 255.0;
 
-        // ys --> (num images x num channels x height x width)
-        let mut ys =
-            Array::uninit((xs.len(), 3, self.height() as usize, self.width() as usize)).into_dyn();
-        // Parallel fill of the uninitialized array
-        ys.as_slice_mut().unwrap().par_iter_mut().for_each(|elem| {
-            *elem = std::mem::MaybeUninit::new(fill_val);
-        });
-        // SAFETY: We've fully initialized `ys`, so we can now assume it’s safe to use.
-        let mut ys = unsafe { ys.assume_init() };
+        // ys --> (num images * num augs x num channels x height x width)
+        let shape = [
+            xs.len() * augs.len(),
+            3,
+            self.height() as usize,
+            self.width() as usize,
+        ];
+        if ys.shape() != shape {
+            *ys = Array::zeros(IxDyn(&shape));
+        }
+        ys.as_slice_mut()
+            .expect("standard layout")
+            .par_iter_mut()
+            .for_each(|elem| *elem = fill_val);
 
         ys.axis_iter_mut(Axis(0))
             .into_par_iter()
-            .zip(xs.par_iter())
-            .for_each(|(mut ys_slice, x)| {
-                // Resize the image
+            .enumerate()
+            .for_each(|(idx, mut ys_slice)| {
+                let x = &xs[idx / augs.len()];
+                let (scale, flip) = augs[idx % augs.len()];
+                // Resize the image, the padding is already in `ys`
+                let resized;
                 let img = match self.task() {
-                    YOLOTask::Classify => x.resize_exact(
-                        self.width(),
-                        self.height(),
-                        image::imageops::FilterType::Triangle,
-                    ),
+                    YOLOTask::Classify => {
+                        resized = x.resize_exact(
+                            self.width(),
+                            self.height(),
+                            image::imageops::FilterType::Triangle,
+                        );
+                        &resized
+                    }
                     _ => {
                         let (w0, h0) = x.dimensions();
                         let w0 = w0 as f32;
                         let h0 = h0 as f32;
-                        let (_, w_new, h_new) =
-                            self.scale_wh(w0, h0, self.width() as f32, self.height() as f32);
-                        if !(w_new == self.width() as f32 && h_new == self.height() as f32) {
-                            x.resize_exact(
+                        let (_, w_new, h_new) = self.scale_wh(
+                            w0,
+                            h0,
+                            self.width() as f32 * scale,
+                            self.height() as f32 * scale,
+                        );
+                        if !(w_new == w0 && h_new == h0) {
+                            resized = x.resize_exact(
                                 w_new as u32,
                                 h_new as u32,
                                 if let YOLOTask::Segment = self.task() {
@@ -167,41 +512,51 @@ impl YOLOv8 {
                                 } else {
                                     image::imageops::FilterType::Triangle
                                 },
-                            )
+                            );
+                            &resized
                         } else {
-                            x.clone()
+                            x
                         }
                     }
                 };
+                let converted;
+                let img = match img.as_rgb8() {
+                    Some(img) => img,
+                    None => {
+                        converted = img.to_rgb8();
+                        &converted
+                    }
+                };
 
-                // Pad to target size
-                let img = multi_capture::pad_to_size(img, self.height(), self.width(), 144);
-
-                // Normalize and reshape to h x w x 3, and copy directly into the ys slice
-                let res = img
-                    .as_rgb8()
-                    .expect("valid RGB8")
-                    .par_iter()
-                    .map(|&b| (b as f32) / 255.0)
-                    .collect::<Vec<_>>();
-
-                let reshaped_res =
-                    Array::from_shape_vec((self.height() as usize, self.width() as usize, 3), res)
-                        .expect("valid matrix")
-                        .permuted_axes([2, 0, 1]);
-
-                ys_slice.assign(&reshaped_res);
+                // Normalize and copy directly into the ys slice, one channel per task;
+                // flipping the padded input keeps the mapping back to the frame a plain mirror
+                let width = self.width() as usize;
+                ys_slice
+                    .axis_iter_mut(Axis(0))
+                    .into_par_iter()
+                    .enumerate()
+                    .for_each(|(c, mut plane)| {
+                        for (y, row) in img.rows().enumerate().take(self.height() as usize) {
+                            for (x, pixel) in row.enumerate().take(width) {
+                                let x = if flip { width - 1 - x } else { x };
+                                plane[[y, x]] = pixel[c] as f32 / 255.0;
+                            }
+                        }
+                    });
             });
-
-        Ok(ys)
     }
 
-    pub fn run(&mut self, xs: &Vec<DynamicImage>, log: bool) -> Result<Vec<YOLOResult>> {
+    pub fn run(&mut self, xs: &[DynamicImage], log: bool) -> Result<Vec<YOLOResult>> {
         let start = Instant::now();
 
-        // pre-process
+        // pre-process, into the input buffer of the last batch
         let t_pre = std::time::Instant::now();
-        let xs_ = self.preprocess(xs)?;
+        let mut input = std::mem::take(&mut self.input);
+        if self.tta.is_empty() {
+            self.preprocess_into(xs, &[(1., false)], &mut input);
+        } else {
+            self.preprocess_into(xs, &self.tta, &mut input);
+        }
         let pre_time = t_pre.elapsed();
         if self.profile && log {
             info!("Preprocess duration: {:?}", pre_time);
@@ -209,7 +564,12 @@ impl YOLOv8 {
 
         // run
         let t_run = std::time::Instant::now();
-        let ys = self.engine.run(xs_, self.profile)?;
+        let mut outputs = std::mem::take(&mut self.outputs);
+        let ran = self
+            .engine
+            .run_into(input.view(), &mut outputs, self.profile);
+        self.input = input;
+        ran?;
         let run_time = t_run.elapsed();
         if self.profile && log {
             info!("Run duration: {:?}", run_time);
@@ -217,13 +577,20 @@ impl YOLOv8 {
 
         // post-process
         let t_post = Instant::now();
-        let ys = self.postprocess(ys, xs)?;
+        let ys = self.postprocess(&outputs, xs);
+        self.outputs = outputs;
+        let ys = ys?;
         let post_time = t_post.elapsed();
         if self.profile && log {
             info!("Postprocess duration: {:?}", post_time);
         }
 
         // log inference times
+        self.times = RunTimes {
+            preprocess: pre_time,
+            inference: run_time,
+            postprocess: post_time,
+        };
         if log {
             let total = format!(
                 "Model inference duration: {:?} (Pre: {:?}, Run: {:?}, Post: {:?})",
@@ -244,32 +611,33 @@ impl YOLOv8 {
 
     pub fn postprocess(
         &self,
-        xs: Vec<Array<f32, IxDyn>>,
+        xs: &[Arc<Array<f32, IxDyn>>],
+        xs0: &[DynamicImage],
+    ) -> Result<Vec<YOLOResult>> {
+        // every result records the model that produced it
+        let mut ys = self.decode(xs, xs0)?;
+        for y in ys.iter_mut() {
+            y.model_id = Some(Arc::clone(&self.model_id));
+        }
+        Ok(ys)
+    }
+
+    fn decode(
+        &self,
+        xs: &[Arc<Array<f32, IxDyn>>],
         xs0: &[DynamicImage],
     ) -> Result<Vec<YOLOResult>> {
         if let YOLOTask::Classify = self.task() {
             let mut ys = Vec::new();
             let preds = &xs[0];
             for batch in preds.axis_iter(Axis(0)) {
-                ys.push(YOLOResult::new(
-                    Some(Embedding::new(batch.into_owned())),
-                    None,
-                    None,
-                    None,
-                ));
+                let probs = Embedding::new(batch.into_owned()).probabilities();
+                ys.push(YOLOResult::new(Some(probs), None, None, None));
             }
             Ok(ys)
-        } else {
+        } else if let YOLOTask::Obb = self.task() {
             const CXYWH_OFFSET: usize = 4; // cxcywh
-            const KPT_STEP: usize = 3; // xyconf
             let preds = &xs[0];
-            let protos = {
-                if xs.len() > 1 {
-                    Some(&xs[1])
-                } else {
-                    None
-                }
-            };
             let ys: Vec<YOLOResult> = preds
                 .axis_iter(Axis(0))
                 .into_par_iter()
@@ -280,20 +648,12 @@ impl YOLOv8 {
                     let ratio = (self.width() as f32 / width_original)
                         .min(self.height() as f32 / height_original);
 
-                    let mut data: Vec<(Bbox, Option<Vec<Point2>>, Option<Vec<f32>>)> = Vec::new();
+                    let mut data: Vec<Obb> = Vec::new();
                     for pred in anchor.axis_iter(Axis(1)) {
                         let bbox = pred.slice(s![0..CXYWH_OFFSET]);
                         let clss = pred.slice(s![CXYWH_OFFSET..CXYWH_OFFSET + self.nc() as usize]);
-                        let kpts = if let YOLOTask::Pose = self.task() {
-                            Some(pred.slice(s![pred.len() - KPT_STEP * self.nk() as usize..]))
-                        } else {
-                            None
-                        };
-                        let coefs = if let YOLOTask::Segment = self.task() {
-                            Some(pred.slice(s![pred.len() - self.nm() as usize..]).to_vec())
-                        } else {
-                            None
-                        };
+                        // angle in radians, right after the class scores
+                        let angle = pred[CXYWH_OFFSET + self.nc() as usize];
 
                         let (id, &confidence) = clss
                             .into_iter()
@@ -301,110 +661,114 @@ impl YOLOv8 {
                             .reduce(|max, x| if x.1 > max.1 { x } else { max })
                             .unwrap();
 
-                        if confidence < self.conf {
+                        if confidence < self.class_conf(id) {
                             continue;
                         }
 
-                        let cx = bbox[0] / ratio;
-                        let cy = bbox[1] / ratio;
-                        let w = bbox[2] / ratio;
-                        let h = bbox[3] / ratio;
-                        let x = cx - w / 2.;
-                        let y = cy - h / 2.;
-                        let y_bbox = Bbox::new(
-                            x.max(0.0f32).min(width_original),
-                            y.max(0.0f32).min(height_original),
-                            w,
-                            h,
+                        data.push(Obb::new(
+                            bbox[0] / ratio,
+                            bbox[1] / ratio,
+                            bbox[2] / ratio,
+                            bbox[3] / ratio,
+                            angle,
                             id,
                             confidence,
-                        );
+                        ));
+                    }
 
-                        let y_kpts = if let Some(kpts) = kpts {
-                            let mut kpts_ = Vec::new();
-                            for i in 0..self.nk() as usize {
-                                let kx = kpts[KPT_STEP * i] / ratio;
-                                let ky = kpts[KPT_STEP * i + 1] / ratio;
-                                let kconf = kpts[KPT_STEP * i + 2];
-                                if kconf < self.kconf {
-                                    kpts_.push(Point2::default());
-                                } else {
-                                    kpts_.push(Point2::new_with_conf(
-                                        kx.max(0.0f32).min(width_original),
-                                        ky.max(0.0f32).min(height_original),
-                                        kconf,
-                                    ));
-                                }
-                            }
-                            Some(kpts_)
-                        } else {
-                            None
-                        };
+                    non_max_suppression_obb(&mut data, self.iou);
+
+                    YOLOResult {
+                        probs: None,
+                        bboxes: None,
+                        keypoints: None,
+                        masks: None,
+                        obbs: if !data.is_empty() { Some(data) } else { None },
+                        model_id: None,
+                    }
+                })
+                .collect();
+            Ok(ys)
+        } else {
+            // every result shares the protos without a copy
+            let protos = if self.task() == &YOLOTask::Segment && xs.len() > 1 {
+                Some(Arc::clone(&xs[1]))
+            } else {
+                None
+            };
+            let preds = &xs[0];
+            // copies of every frame made by test-time augmentation, next to each other
+            let augs: &[(f32, bool)] = if self.tta.is_empty() {
+                &[(1., false)]
+            } else {
+                &self.tta
+            };
+            let ys: Vec<YOLOResult> = (0..preds.shape()[0] / augs.len())
+                .into_par_iter()
+                .map(|idx| {
+                    let width_original = xs0[idx].width() as f32;
+                    let height_original = xs0[idx].height() as f32;
+                    let ratio = (self.width() as f32 / width_original)
+                        .min(self.height() as f32 / height_original);
 
-                        data.push((y_bbox, y_kpts, coefs));
+                    // candidates of all copies are mapped back to the frame and suppressed together
+                    let mut data = Vec::new();
+                    for (i, &(scale, flip)) in augs.iter().enumerate() {
+                        let b = idx * augs.len() + i;
+                        data.extend(match self.engine.end_to_end() {
+                            Some(_) => self.end_to_end_candidates(
+                                xs,
+                                b,
+                                ratio * scale,
+                                flip,
+                                width_original,
+                                height_original,
+                            ),
+                            None => self.candidates(
+                                preds.index_axis(Axis(0), b),
+                                ratio * scale,
+                                flip,
+                                width_original,
+                                height_original,
+                            ),
+                        });
                     }
 
-                    non_max_suppression(&mut data, self.iou);
+                    // NMS-free models still need it to merge the copies of test-time augmentation
+                    let nms_free =
+                        self.engine.family().nms_free() || self.engine.end_to_end().is_some();
+                    if !nms_free || augs.len() > 1 {
+                        non_max_suppression(&mut data, self.iou);
+                    }
 
                     let mut y_bboxes = Vec::new();
                     let mut y_kpts = Vec::new();
-                    let mut y_masks = Vec::new();
+                    let mut y_coefs = Vec::new();
                     for elem in data.into_iter() {
                         if let Some(kpts) = elem.1 {
                             y_kpts.push(kpts)
                         }
 
                         if let Some(coefs) = elem.2 {
-                            let proto = protos.unwrap().slice(s![idx, .., .., ..]);
-                            let (nm, nh, nw) = proto.dim();
-
-                            let coefs = Array::from_shape_vec((1, nm), coefs).unwrap();
-                            let proto = proto.to_owned().into_shape((nm, nh * nw)).unwrap();
-                            let mask = coefs.dot(&proto).into_shape((nh, nw, 1)).unwrap();
-
-                            let mask_im: ImageBuffer<image::Luma<_>, Vec<f32>> =
-                                ImageBuffer::from_raw(nw as u32, nh as u32, mask.into_raw_vec())
-                                    .expect("Cannot create image from ndarray");
-
-                            let mut mask_im = image::DynamicImage::from(mask_im);
-
-                            let (_, w_mask, h_mask) = self.scale_wh(
-                                width_original,
-                                height_original,
-                                nw as f32,
-                                nh as f32,
-                            );
-                            let mask_cropped = mask_im.crop(0, 0, w_mask as u32, h_mask as u32);
-                            let mask_original = mask_cropped.resize_exact(
-                                width_original as u32,
-                                height_original as u32,
-                                match self.task() {
-                                    YOLOTask::Segment => image::imageops::FilterType::CatmullRom,
-                                    _ => image::imageops::FilterType::Triangle,
-                                },
-                            );
-
-                            let mut mask_original_cropped = mask_original.into_luma8();
-                            for y in 0..height_original as usize {
-                                for x in 0..width_original as usize {
-                                    if x < elem.0.xmin() as usize
-                                        || x > elem.0.xmax() as usize
-                                        || y < elem.0.ymin() as usize
-                                        || y > elem.0.ymax() as usize
-                                    {
-                                        mask_original_cropped.put_pixel(
-                                            x as u32,
-                                            y as u32,
-                                            image::Luma([0u8]),
-                                        );
-                                    }
-                                }
-                            }
-                            y_masks.push(mask_original_cropped.into_raw());
+                            y_coefs.extend(coefs);
                         }
                         y_bboxes.push(elem.0);
                     }
 
+                    // masks are only decoded when someone asks for them
+                    let y_masks = match protos {
+                        Some(ref protos) if !y_bboxes.is_empty() => Some(Masks::new(
+                            Arc::clone(protos),
+                            idx,
+                            Array::from_shape_vec((y_bboxes.len(), self.nm() as usize), y_coefs)
+                                .expect("one row of coefficients per bbox"),
+                            y_bboxes.clone(),
+                            width_original as u32,
+                            height_original as u32,
+                        )),
+                        _ => None,
+                    };
+
                     YOLOResult {
                         probs: None,
                         bboxes: if !y_bboxes.is_empty() {
@@ -417,11 +781,9 @@ impl YOLOv8 {
                         } else {
                             None
                         },
-                        masks: if !y_masks.is_empty() {
-                            Some(y_masks)
-                        } else {
-                            None
-                        },
+                        masks: y_masks,
+                        obbs: None,
+                        model_id: None,
                     }
                 })
                 .collect();
@@ -429,6 +791,154 @@ impl YOLOv8 {
         }
     }
 
+    fn candidates(
+        &self,
+        anchor: ArrayView<f32, IxDyn>,
+        ratio: f32,
+        flip: bool,
+        width_original: f32,
+        height_original: f32,
+    ) -> Vec<(Bbox, Option<Vec<Point2>>, Option<Vec<f32>>)> {
+        // every prediction of one image above its class threshold, in original image coordinates
+        const CXYWH_OFFSET: usize = 4; // cxcywh
+        const KPT_STEP: usize = 3; // xyconf
+        let family = self.engine.family();
+        let axis = if family.transposed() {
+            Axis(0)
+        } else {
+            Axis(1)
+        };
+        let mut data = Vec::new();
+        for pred in anchor.axis_iter(axis) {
+            let (bbox, id, confidence) = match family {
+                ModelFamily::Yolov10 => {
+                    // x1y1x2y2, score, class id
+                    let id = pred[5] as usize;
+                    if id >= self.nc() as usize {
+                        continue;
+                    }
+                    let bbox = [
+                        (pred[0] + pred[2]) / 2.,
+                        (pred[1] + pred[3]) / 2.,
+                        pred[2] - pred[0],
+                        pred[3] - pred[1],
+                    ];
+                    (bbox, id, pred[4])
+                }
+                _ => {
+                    // class scores after cxcywh, and the objectness of YOLOv5
+                    let offset = CXYWH_OFFSET + family.objectness() as usize;
+                    let clss = pred.slice(s![offset..offset + self.nc() as usize]);
+                    let (id, &confidence) = clss
+                        .into_iter()
+                        .enumerate()
+                        .reduce(|max, x| if x.1 > max.1 { x } else { max })
+                        .unwrap();
+                    let confidence = if family.objectness() {
+                        confidence * pred[CXYWH_OFFSET]
+                    } else {
+                        confidence
+                    };
+                    ([pred[0], pred[1], pred[2], pred[3]], id, confidence)
+                }
+            };
+            let kpts = if let YOLOTask::Pose = self.task() {
+                Some(pred.slice(s![pred.len() - KPT_STEP * self.nk() as usize..]))
+            } else {
+                None
+            };
+            let coefs = if let YOLOTask::Segment = self.task() {
+                Some(pred.slice(s![pred.len() - self.nm() as usize..]).to_vec())
+            } else {
+                None
+            };
+
+            if confidence < self.class_conf(id) {
+                continue;
+            }
+
+            let [x, y, w, h] = frame_bbox(
+                bbox,
+                ratio,
+                flip,
+                self.width() as f32,
+                width_original,
+                height_original,
+            );
+            let y_bbox = Bbox::new(x, y, w, h, id, confidence);
+
+            let y_kpts = if let Some(kpts) = kpts {
+                let mut kpts_ = Vec::new();
+                for i in 0..self.nk() as usize {
+                    let kx = kpts[KPT_STEP * i] / ratio;
+                    let ky = kpts[KPT_STEP * i + 1] / ratio;
+                    let kconf = kpts[KPT_STEP * i + 2];
+                    if kconf < self.kconf {
+                        kpts_.push(Point2::default());
+                    } else {
+                        kpts_.push(Point2::new_with_conf(
+                            kx.max(0.0f32).min(width_original),
+                            ky.max(0.0f32).min(height_original),
+                            kconf,
+                        ));
+                    }
+                }
+                Some(kpts_)
+            } else {
+                None
+            };
+
+            data.push((y_bbox, y_kpts, coefs));
+        }
+        data
+    }
+
+    fn end_to_end_candidates(
+        &self,
+        xs: &[Arc<Array<f32, IxDyn>>],
+        b: usize,
+        ratio: f32,
+        flip: bool,
+        width_original: f32,
+        height_original: f32,
+    ) -> Vec<(Bbox, Option<Vec<Point2>>, Option<Vec<f32>>)> {
+        // detections of image `b` as the NMS in the graph left them, only mapped back to the frame
+        let end_to_end = match self.engine.end_to_end() {
+            Some(end_to_end) => end_to_end,
+            None => return vec![],
+        };
+        let boxes = xs[end_to_end.boxes].index_axis(Axis(0), b);
+        let scores = xs[end_to_end.scores].index_axis(Axis(0), b);
+        let classes = xs[end_to_end.classes].index_axis(Axis(0), b);
+        let num_dets = xs[end_to_end.num_dets]
+            .index_axis(Axis(0), b)
+            .iter()
+            .next()
+            .map_or(0, |&n| n as usize)
+            .min(boxes.shape()[0]);
+
+        let mut data = Vec::new();
+        for i in 0..num_dets {
+            let id = classes[[i]] as usize;
+            let confidence = scores[[i]];
+            if id >= self.nc() as usize || confidence < self.class_conf(id) {
+                continue;
+            }
+            let (x1, y1, x2, y2) = (boxes[[i, 0]], boxes[[i, 1]], boxes[[i, 2]], boxes[[i, 3]]);
+            let bbox = [(x1 + x2) / 2., (y1 + y2) / 2., x2 - x1, y2 - y1];
+            let [x, y, w, h] = frame_bbox(
+                bbox,
+                ratio,
+                flip,
+                self.width() as f32,
+                width_original,
+                height_original,
+            );
+            data.push((Bbox::new(x, y, w, h, id, confidence), None, None));
+        }
+        data
+    }
+
     pub fn plot(
         &self,
         y: &YOLOResult,
@@ -439,26 +949,127 @@ impl YOLOv8 {
 
         let mut img = img0.to_rgb8();
 
+        // draw masks, blended with the class color
+        if let Some((masks, bboxes)) = y.masks().zip(y.bboxes()) {
+            for (mask, bbox) in masks.decode().iter().zip(bboxes.iter()) {
+                let color = image::Rgb(self.color_palette[bbox.id()].into());
+                for (i, &m) in mask.data().iter().enumerate() {
+                    if m > 0 {
+                        let x = mask.x() + i as u32 % mask.width();
+                        let y = mask.y() + i as u32 / mask.width();
+                        let pixel = img.get_pixel_mut(x, y);
+                        *pixel = imageproc::pixelops::weighted_sum(
+                            *pixel,
+                            color,
+                            1. - MASK_ALPHA,
+                            MASK_ALPHA,
+                        );
+                    }
+                }
+            }
+        }
+
         // draw bboxes & keypoints
         if let Some(bboxes) = y.bboxes() {
-            for (_idx, bbox) in bboxes.iter().enumerate() {
-                // rect
-                imageproc::drawing::draw_hollow_rect_mut(
+            draw_bboxes(&mut img, bboxes, &self.names, &self.color_palette, &font);
+        }
+
+        // draw skeletons, skipping keypoints below kconf
+        if let Some((keypoints, bboxes)) = y.keypoints().zip(y.bboxes()) {
+            let radius = (img.width().max(img.height()) / 200).max(2) as i32;
+            for (kpts, bbox) in keypoints.iter().zip(bboxes.iter()) {
+                // edges
+                for &(a, b) in self.skeleton.iter() {
+                    let (p, q) = (&kpts[a], &kpts[b]);
+                    if p.confidence() < self.kconf || q.confidence() < self.kconf {
+                        continue;
+                    }
+                    imageproc::drawing::draw_line_segment_mut(
+                        &mut img,
+                        (p.x(), p.y()),
+                        (q.x(), q.y()),
+                        image::Rgb(self.color_palette[bbox.id()].into()),
+                    );
+                }
+
+                // points
+                for (i, kpt) in kpts.iter().enumerate() {
+                    if kpt.confidence() < self.kconf {
+                        continue;
+                    }
+                    imageproc::drawing::draw_filled_circle_mut(
+                        &mut img,
+                        (kpt.x() as i32, kpt.y() as i32),
+                        radius,
+                        image::Rgb(self.kpt_palette[i].into()),
+                    );
+                }
+            }
+        }
+
+        // draw top-k classes as a panel in the top left corner
+        if let Some(classes) = self.classes(y) {
+            let legend_size = img.width().max(img.height()) / 40;
+            let scale = rusttype::Scale::uniform(legend_size as f32 - 1.);
+            let lines: Vec<_> = classes
+                .iter()
+                .map(|(name, probability)| format!("{} {:.2}", name, probability))
+                .collect();
+            let panel_width = lines
+                .iter()
+                .map(|line| imageproc::drawing::text_size(scale, &font, line).0)
+                .max()
+                .unwrap_or(0)
+                + 8;
+            imageproc::drawing::draw_filled_rect_mut(
+                &mut img,
+                imageproc::rect::Rect::at(0, 0).of_size(
+                    panel_width.max(1) as u32,
+                    lines.len() as u32 * legend_size + 8,
+                ),
+                image::Rgb([0, 0, 0]),
+            );
+            for (i, line) in lines.iter().enumerate() {
+                imageproc::drawing::draw_text_mut(
                     &mut img,
-                    imageproc::rect::Rect::at(bbox.xmin() as i32, bbox.ymin() as i32)
-                        .of_size(bbox.width() as u32, bbox.height() as u32),
-                    image::Rgb(self.color_palette[bbox.id()].into()),
+                    image::Rgb([255, 255, 255]),
+                    4,
+                    (4 + i as u32 * legend_size) as i32,
+                    scale,
+                    &font,
+                    line,
                 );
+            }
+        }
 
-                // text
-                let legend = format!("{} {:.2}%", self.names[bbox.id()], bbox.confidence());
+        // draw oriented bboxes
+        if let Some(obbs) = y.obbs() {
+            for obb in obbs.iter() {
+                // rotated rect
+                let vertices = obb.vertices();
+                for i in 0..vertices.len() {
+                    let (p, q) = (&vertices[i], &vertices[(i + 1) % vertices.len()]);
+                    imageproc::drawing::draw_line_segment_mut(
+                        &mut img,
+                        (p.x(), p.y()),
+                        (q.x(), q.y()),
+                        image::Rgb(self.color_palette[obb.id()].into()),
+                    );
+                }
+
+                // text, anchored at the top-most vertex
+                let top = vertices
+                    .iter()
+                    .min_by(|a, b| a.y().partial_cmp(&b.y()).unwrap())
+                    .unwrap();
+                let legend = format!("{} {:.2}%", self.names[obb.id()], obb.confidence());
                 let scale = 40;
                 let legend_size = img.width().max(img.height()) / scale;
                 imageproc::drawing::draw_text_mut(
                     &mut img,
-                    image::Rgb(self.color_palette[bbox.id()].into()),
-                    bbox.xmin() as i32,
-                    (bbox.ymin() - legend_size as f32) as i32,
+                    image::Rgb(self.color_palette[obb.id()].into()),
+                    top.x() as i32,
+                    (top.y() - legend_size as f32) as i32,
                     rusttype::Scale::uniform(legend_size as f32 - 1.),
                     &font,
                     &legend,
@@ -557,6 +1168,10 @@ impl YOLOv8 {
         &self.engine
     }
 
+    pub fn model_id(&self) -> &str {
+        &self.model_id
+    }
+
     pub fn conf(&self) -> f32 {
         self.conf
     }
@@ -569,6 +1184,27 @@ impl YOLOv8 {
         &mut self.conf
     }
 
+    pub fn classes(&self, y: &YOLOResult) -> Option<Vec<(String, f32)>> {
+        // top-k (class name, probability) pairs of a classification result
+        y.classes(&self.names, self.topk)
+    }
+
+    pub fn topk(&self) -> usize {
+        self.topk
+    }
+
+    pub fn times(&self) -> RunTimes {
+        self.times
+    }
+
+    pub fn self_test(&self) -> Option<&SelfTestReport> {
+        self.self_test.as_ref()
+    }
+
+    pub fn tta(&self) -> &Vec<(f32, bool)> {
+        &self.tta
+    }
+
     pub fn kconf(&self) -> f32 {
         self.kconf
     }
@@ -577,6 +1213,45 @@ impl YOLOv8 {
         self.iou
     }
 
+    pub fn class_conf(&self, id: usize) -> f32 {
+        // per-class threshold if there is one, hidden classes never pass
+        match self.class_thresholds.get(id) {
+            Some(Some(conf)) => *conf,
+            _ => self.conf,
+        }
+    }
+
+    pub fn thresholds(&self) -> Thresholds {
+        Thresholds {
+            conf: self.conf,
+            iou: self.iou,
+            kconf: self.kconf,
+            class_conf: self.class_conf.clone(),
+            hidden_classes: self.hidden_classes.clone(),
+        }
+    }
+
+    pub fn set_thresholds(&mut self, thresholds: Thresholds) -> Result<()> {
+        thresholds.validate(&self.names)?;
+        self.class_thresholds = self
+            .names
+            .iter()
+            .map(|name| {
+                if thresholds.hidden_classes.contains(name) {
+                    Some(f32::INFINITY)
+                } else {
+                    thresholds.class_conf.get(name).copied()
+                }
+            })
+            .collect();
+        self.conf = thresholds.conf;
+        self.iou = thresholds.iou;
+        self.kconf = thresholds.kconf;
+        self.class_conf = thresholds.class_conf;
+        self.hidden_classes = thresholds.hidden_classes;
+        Ok(())
+    }
+
     pub fn task(&self) -> &YOLOTask {
         &self.task
     }
@@ -608,4 +1283,70 @@ impl YOLOv8 {
     pub fn names(&self) -> &Vec<String> {
         &self.names
     }
+
+    pub fn color_palette(&self) -> &Vec<(u8, u8, u8)> {
+        &self.color_palette
+    }
+
+    pub fn skeleton(&self) -> &Vec<(usize, usize)> {
+        &self.skeleton
+    }
+}
+
+pub fn frame_bbox(
+    bbox: [f32; 4],
+    ratio: f32,
+    flip: bool,
+    input_width: f32,
+    width_original: f32,
+    height_original: f32,
+) -> [f32; 4] {
+    // cxcywh in model input coordinates to xywh in the original image; `ratio` is the
+    // letterbox ratio times the test-time augmentation scale, and a flipped input is
+    // mirrored back before scaling
+    let cx = if flip { input_width - bbox[0] } else { bbox[0] } / ratio;
+    let cy = bbox[1] / ratio;
+    let w = bbox[2] / ratio;
+    let h = bbox[3] / ratio;
+    let x = cx - w / 2.;
+    let y = cy - h / 2.;
+    [
+        x.max(0.0f32).min(width_original),
+        y.max(0.0f32).min(height_original),
+        w,
+        h,
+    ]
+}
+
+// Draws bboxes with their class name and confidence.
+pub fn draw_bboxes(
+    img: &mut ImageBuffer<image::Rgb<u8>, Vec<u8>>,
+    bboxes: &[Bbox],
+    names: &[String],
+    color_palette: &[(u8, u8, u8)],
+    font: &rusttype::Font,
+) {
+    for bbox in bboxes.iter() {
+        // rect
+        imageproc::drawing::draw_hollow_rect_mut(
+            img,
+            imageproc::rect::Rect::at(bbox.xmin() as i32, bbox.ymin() as i32)
+                .of_size(bbox.width() as u32, bbox.height() as u32),
+            image::Rgb(color_palette[bbox.id()].into()),
+        );
+
+        // text
+        let legend = format!("{} {:.2}%", names[bbox.id()], bbox.confidence());
+        let scale = 40;
+        let legend_size = img.width().max(img.height()) / scale;
+        imageproc::drawing::draw_text_mut(
+            img,
+            image::Rgb(color_palette[bbox.id()].into()),
+            bbox.xmin() as i32,
+            (bbox.ymin() - legend_size as f32) as i32,
+            rusttype::Scale::uniform(legend_size as f32 - 1.),
+            font,
+            &legend,
+        );
+    }
 }
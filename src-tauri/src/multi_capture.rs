@@ -1,4 +1,4 @@
-use anyhow::{bail, Error, Result};
+use anyhow::{anyhow, bail, Error, Result};
 use image::{DynamicImage, GenericImage, GenericImageView, ImageFormat, Rgb, RgbImage};
 use mat2image::ToImage;
 use opencv::videoio::CAP_ANY;
@@ -175,6 +175,49 @@ pub fn setup_captures(
     recievers
 }
 
+// Frames of a video file, read one at a time up to `limit`, for runs without cameras
+pub struct VideoFrames {
+    cap: videoio::VideoCapture,
+    img: Mat,
+    left: usize,
+}
+
+impl VideoFrames {
+    pub fn open(path: &str, limit: usize) -> Result<Self> {
+        let cap = videoio::VideoCapture::from_file(path, CAP_ANY)?;
+        if !cap.is_opened()? {
+            bail!("Error: Could not open video {path}.");
+        }
+        Ok(Self {
+            cap,
+            img: Mat::default(),
+            left: limit,
+        })
+    }
+}
+
+impl Iterator for VideoFrames {
+    type Item = Result<DynamicImage>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.left == 0 {
+            return None;
+        }
+        match self.cap.read(&mut self.img) {
+            Ok(true) => {
+                self.left -= 1;
+                Some(
+                    self.img
+                        .to_image_par()
+                        .map_err(|_| anyhow!("Error: Could not convert Mat to DynamicImage.")),
+                )
+            }
+            Ok(false) => None,
+            Err(e) => Some(Err(e.into())),
+        }
+    }
+}
+
 pub fn convert_to_bytes(img: &DynamicImage, format: ImageFormat) -> Vec<u8> {
     let mut buf = Vec::new();
     let mut writer = std::io::BufWriter::new(Cursor::new(&mut buf));
@@ -0,0 +1,216 @@
+use ort::tensor::TensorElementDataType;
+
+use crate::LoadError;
+
+// field numbers of the ONNX protobuf messages read here, see onnx/onnx.proto
+const MODEL_GRAPH: u32 = 7;
+const GRAPH_INITIALIZER: u32 = 5;
+const GRAPH_INPUT: u32 = 11;
+const TENSOR_NAME: u32 = 8;
+const VALUE_INFO_NAME: u32 = 1;
+const VALUE_INFO_TYPE: u32 = 2;
+const TYPE_TENSOR: u32 = 1;
+const TENSOR_TYPE_ELEM: u32 = 1;
+const TENSOR_TYPE_SHAPE: u32 = 2;
+const SHAPE_DIM: u32 = 1;
+const DIM_VALUE: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OnnxInput {
+    // a graph input as declared in the model file, -1 for dynamic dims
+    pub name: String,
+    pub dtype: TensorElementDataType,
+    pub shape: Vec<i32>,
+}
+
+enum Field<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+struct Reader<'a> {
+    // protobuf wire format, just enough to walk the fields of a message
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    fn varint(&mut self) -> Result<u64, LoadError> {
+        let mut x = 0u64;
+        for shift in (0..64).step_by(7) {
+            let b = *self.buf.get(self.pos).ok_or_else(truncated)?;
+            self.pos += 1;
+            x |= ((b & 0x7f) as u64) << shift;
+            if b < 0x80 {
+                return Ok(x);
+            }
+        }
+        Err(LoadError::Onnx("varint too long".to_string()))
+    }
+
+    fn skip(&mut self, n: usize) -> Result<&'a [u8], LoadError> {
+        let end = self.pos.checked_add(n).ok_or_else(truncated)?;
+        let bytes = self.buf.get(self.pos..end).ok_or_else(truncated)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn next(&mut self) -> Result<Option<(u32, Field<'a>)>, LoadError> {
+        if self.pos >= self.buf.len() {
+            return Ok(None);
+        }
+        let key = self.varint()?;
+        let field = match key & 7 {
+            0 => Field::Varint(self.varint()?),
+            1 => {
+                self.skip(8)?;
+                Field::Fixed
+            }
+            2 => {
+                let n = self.varint()? as usize;
+                Field::Bytes(self.skip(n)?)
+            }
+            5 => {
+                self.skip(4)?;
+                Field::Fixed
+            }
+            t => return Err(LoadError::Onnx(format!("unsupported wire type {t}"))),
+        };
+        Ok(Some(((key >> 3) as u32, field)))
+    }
+}
+
+fn truncated() -> LoadError {
+    LoadError::Onnx("truncated protobuf".to_string())
+}
+
+fn string(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).to_string()
+}
+
+fn dtype(elem_type: u64) -> Result<TensorElementDataType, LoadError> {
+    // TensorProto.DataType
+    Ok(match elem_type {
+        1 => TensorElementDataType::Float32,
+        2 => TensorElementDataType::Uint8,
+        3 => TensorElementDataType::Int8,
+        4 => TensorElementDataType::Uint16,
+        5 => TensorElementDataType::Int16,
+        6 => TensorElementDataType::Int32,
+        7 => TensorElementDataType::Int64,
+        9 => TensorElementDataType::Bool,
+        10 => TensorElementDataType::Float16,
+        11 => TensorElementDataType::Float64,
+        12 => TensorElementDataType::Uint32,
+        13 => TensorElementDataType::Uint64,
+        16 => TensorElementDataType::Bfloat16,
+        x => return Err(LoadError::Dtype(format!("ONNX elem_type {x}"))),
+    })
+}
+
+/*
+Reads the graph inputs straight from the model bytes, so the input shapes
+needed to set up the execution provider are known before the one session
+is built. Initializers listed as inputs (older exporters do that) are
+skipped, the same as ORT does.
+*/
+pub fn inputs(model: &[u8]) -> Result<Vec<OnnxInput>, LoadError> {
+    let mut graph = None;
+    let mut reader = Reader::new(model);
+    while let Some((number, field)) = reader.next()? {
+        if let (MODEL_GRAPH, Field::Bytes(bytes)) = (number, field) {
+            graph = Some(bytes);
+        }
+    }
+    let graph = graph.ok_or_else(|| LoadError::Onnx("no graph found".to_string()))?;
+
+    let mut initializers = vec![];
+    let mut value_infos = vec![];
+    let mut reader = Reader::new(graph);
+    while let Some((number, field)) = reader.next()? {
+        match (number, field) {
+            (GRAPH_INITIALIZER, Field::Bytes(bytes)) => {
+                let mut tensor = Reader::new(bytes);
+                while let Some((number, field)) = tensor.next()? {
+                    if let (TENSOR_NAME, Field::Bytes(name)) = (number, field) {
+                        initializers.push(string(name));
+                    }
+                }
+            }
+            (GRAPH_INPUT, Field::Bytes(bytes)) => value_infos.push(bytes),
+            _ => {}
+        }
+    }
+
+    let mut inputs = vec![];
+    for bytes in value_infos {
+        let input = value_info(bytes)?;
+        if !initializers.contains(&input.name) {
+            inputs.push(input);
+        }
+    }
+    if inputs.is_empty() {
+        return Err(LoadError::Onnx("the graph has no inputs".to_string()));
+    }
+    Ok(inputs)
+}
+
+fn value_info(bytes: &[u8]) -> Result<OnnxInput, LoadError> {
+    // ValueInfoProto -> TypeProto -> TypeProto.Tensor -> TensorShapeProto
+    let mut name = String::new();
+    let mut elem_type = 0;
+    let mut shape = vec![];
+    let mut reader = Reader::new(bytes);
+    while let Some((number, field)) = reader.next()? {
+        match (number, field) {
+            (VALUE_INFO_NAME, Field::Bytes(bytes)) => name = string(bytes),
+            (VALUE_INFO_TYPE, Field::Bytes(bytes)) => {
+                let mut type_proto = Reader::new(bytes);
+                while let Some((number, field)) = type_proto.next()? {
+                    if let (TYPE_TENSOR, Field::Bytes(bytes)) = (number, field) {
+                        let mut tensor = Reader::new(bytes);
+                        while let Some((number, field)) = tensor.next()? {
+                            match (number, field) {
+                                (TENSOR_TYPE_ELEM, Field::Varint(x)) => elem_type = x,
+                                (TENSOR_TYPE_SHAPE, Field::Bytes(bytes)) => shape = dims(bytes)?,
+                                _ => {}
+                            }
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(OnnxInput {
+        dtype: dtype(elem_type)?,
+        name,
+        shape,
+    })
+}
+
+fn dims(bytes: &[u8]) -> Result<Vec<i32>, LoadError> {
+    // a dim is either a value or a symbolic name, the latter is dynamic
+    let mut shape = vec![];
+    let mut reader = Reader::new(bytes);
+    while let Some((number, field)) = reader.next()? {
+        if let (SHAPE_DIM, Field::Bytes(bytes)) = (number, field) {
+            let mut value = -1i32;
+            let mut dim = Reader::new(bytes);
+            while let Some((number, field)) = dim.next()? {
+                if let (DIM_VALUE, Field::Varint(x)) = (number, field) {
+                    if x > 0 {
+                        value = x as i32;
+                    }
+                }
+            }
+            shape.push(value);
+        }
+    }
+    Ok(shape)
+}
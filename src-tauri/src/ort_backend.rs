@@ -1,19 +1,224 @@
 use anyhow::Result;
 use clap::ValueEnum;
 use half::f16;
-use ndarray::{Array, CowArray, IxDyn};
-use ort::execution_providers::{CUDAExecutionProviderOptions, TensorRTExecutionProviderOptions};
-use ort::tensor::TensorElementDataType;
-use ort::{Environment, ExecutionProvider, Session, SessionBuilder, Value};
-use regex::Regex;
+use log::{info, warn};
+use ndarray::{Array, ArrayView, Axis, CowArray, IxDyn, Slice, Zip};
+use ort::execution_providers::{
+    CPUExecutionProviderOptions, CUDAExecutionProviderOptions, TensorRTExecutionProviderOptions,
+};
+use ort::tensor::{IntoTensorElementDataType, TensorElementDataType};
+use ort::{
+    AllocatorType, Environment, ExecutionProvider, GraphOptimizationLevel, Session, SessionBuilder,
+    Value,
+};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use yaml_rust::Yaml;
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, serde::Deserialize)]
+use crate::{metadata, onnx, LoadError};
+
+// timed batches per mode when deciding whether the session pool splits batches
+const SPLIT_TIMING_RUNS: u32 = 3;
+
+#[derive(
+    Debug, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, serde::Serialize, serde::Deserialize,
+)]
 pub enum YOLOTask {
     // YOLO tasks
     Classify,
     Detect,
     Pose,
     Segment,
+    Obb,
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum ModelFamily {
+    // layout of the first output of detection models:
+    // YOLOv5: [B, anchors, 4 + 1 + nc], with objectness
+    // YOLOv8 and YOLO11: [B, 4 + nc, anchors]
+    // YOLOv10: [B, max_det, 6] of x1y1x2y2, score and class, NMS-free
+    Yolov5,
+    #[default]
+    Yolov8,
+    Yolov10,
+    Yolo11,
+}
+
+impl ModelFamily {
+    pub fn transposed(&self) -> bool {
+        // predictions along axis 1 of the output instead of axis 2
+        matches!(self, Self::Yolov5 | Self::Yolov10)
+    }
+
+    pub fn objectness(&self) -> bool {
+        matches!(self, Self::Yolov5)
+    }
+
+    pub fn nms_free(&self) -> bool {
+        matches!(self, Self::Yolov10)
+    }
+
+    pub fn guess(description: Option<&str>, task: &YOLOTask, output: &[i32]) -> Self {
+        // Ultralytics exports name the model in the description, e.g.
+        // `Ultralytics YOLO11n model trained on coco.yaml`; they all share the
+        // YOLOv8 layout apart from YOLOv10, the anchor-free YOLOv5u included
+        if let Some(description) = description {
+            let description = description.to_lowercase();
+            if description.contains("ultralytics") {
+                return if description.contains("yolov10") {
+                    Self::Yolov10
+                } else if description.contains("yolo11") {
+                    Self::Yolo11
+                } else {
+                    if !description.contains("yolov8") {
+                        warn!(
+                            "Unknown Ultralytics model `{description}`, decoding it as YOLOv8. \
+                             Set `family` in the args toml if it has another layout."
+                        );
+                    }
+                    Self::Yolov8
+                };
+            }
+        }
+
+        // otherwise by the first output: 300 rows of 6 values by default for YOLOv10,
+        // more anchors than values per anchor for YOLOv5
+        let detect = match task {
+            YOLOTask::Detect => true,
+            YOLOTask::Segment => false,
+            _ => return Self::Yolov8,
+        };
+        if output.len() != 3 {
+            Self::Yolov8
+        } else if detect && output[1] == 300 && output[2] == 6 {
+            Self::Yolov10
+        } else if output[2] > 0 && (output[1] == -1 || output[1] > output[2]) {
+            Self::Yolov5
+        } else {
+            Self::Yolov8
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct EndToEnd {
+    // output indices of a model with NMS inside the graph:
+    // num_dets [B, 1], boxes [B, max_det, 4] as x1y1x2y2, scores and classes [B, max_det]
+    pub num_dets: usize,
+    pub boxes: usize,
+    pub scores: usize,
+    pub classes: usize,
+}
+
+impl EndToEnd {
+    pub fn find(names: &[String], shapes: &[Vec<i32>]) -> Option<Self> {
+        // by name and rank, e.g. `num_dets`, `det_boxes`, `det_scores` and `det_classes`
+        // of TensorRT's EfficientNMS, or `boxes`, `scores` and `labels`
+        let find = |keys: &[&str], ranks: &[usize]| {
+            names.iter().zip(shapes.iter()).position(|(name, shape)| {
+                let name = name.to_lowercase();
+                keys.iter().any(|key| name.contains(key)) && ranks.contains(&shape.len())
+            })
+        };
+        let end_to_end = Self {
+            num_dets: find(&["num_det"], &[1, 2])?,
+            boxes: find(&["boxes"], &[3])?,
+            scores: find(&["scores"], &[2])?,
+            classes: find(&["classes", "labels"], &[2])?,
+        };
+        if shapes[end_to_end.boxes][2] == 4 {
+            Some(end_to_end)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum OptLevel {
+    // graph optimization level
+    Disable,
+    Basic,
+    Extended,
+    #[default]
+    All,
+}
+
+impl OptLevel {
+    fn level(&self) -> GraphOptimizationLevel {
+        match self {
+            OptLevel::Disable => GraphOptimizationLevel::Disable,
+            OptLevel::Basic => GraphOptimizationLevel::Level1,
+            OptLevel::Extended => GraphOptimizationLevel::Level2,
+            OptLevel::All => GraphOptimizationLevel::Level3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct CpuOptions {
+    // ORT session options, None leaves the ORT default
+    pub intra_threads: Option<i16>,
+    pub inter_threads: Option<i16>,
+    pub opt_level: OptLevel,
+    pub parallel: bool,
+    pub mem_arena: bool,
+    pub mem_pattern: bool,
+}
+
+impl Default for CpuOptions {
+    fn default() -> Self {
+        Self {
+            intra_threads: None,
+            inter_threads: None,
+            opt_level: OptLevel::All,
+            parallel: false,
+            mem_arena: true,
+            mem_pattern: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct PoolOptions {
+    // sessions of the same model, each with its own thread budget
+    pub size: usize,
+    pub threads: Option<i16>,
+    pub split: Option<bool>,
+}
+
+impl Default for PoolOptions {
+    fn default() -> Self {
+        Self {
+            size: 1,
+            threads: None,
+            split: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+pub struct QuantParams {
+    // linear quantization of an integer tensor: real = (q - zero_point) * scale
+    pub scale: f32,
+    pub zero_point: i32,
+}
+
+impl QuantParams {
+    fn quantize(&self, x: f32, min: i32, max: i32) -> i32 {
+        ((x / self.scale).round() as i32 + self.zero_point).clamp(min, max)
+    }
+
+    fn dequantize(&self, q: i32) -> f32 {
+        (q - self.zero_point) as f32 * self.scale
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
@@ -24,7 +229,7 @@ pub enum OrtEP {
     Trt(u32),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Batch {
     pub opt: u32,
     pub min: u32,
@@ -51,35 +256,41 @@ pub struct OrtInputs {
 }
 
 impl OrtInputs {
-    pub fn new(session: &Session) -> Self {
+    pub fn new(model: &[u8]) -> Result<Self, LoadError> {
+        // read from the model bytes, before any session is built
         let mut shapes = Vec::new();
         let mut dtypes = Vec::new();
         let mut names = Vec::new();
-        for i in session.inputs.iter() {
-            let shape: Vec<i32> = i
-                .dimensions()
-                .map(|x| if let Some(x) = x { x as i32 } else { -1i32 })
-                .collect();
-            shapes.push(shape);
-            dtypes.push(i.input_type);
-            names.push(i.name.clone());
+        for i in onnx::inputs(model)?.into_iter() {
+            shapes.push(i.shape);
+            dtypes.push(i.dtype);
+            names.push(i.name);
         }
-        Self {
+        Ok(Self {
             shapes,
             dtypes,
             names,
             ..Default::default()
-        }
+        })
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct OrtConfig {
     // ORT config
     pub f: String,
+    pub bytes: Option<Arc<[u8]>>,
+    pub optimized: Option<String>,
+    pub cpu: CpuOptions,
+    pub pool: PoolOptions,
     pub task: Option<YOLOTask>,
+    pub family: Option<ModelFamily>,
     pub ep: OrtEP,
     pub trt_fp16: bool,
+    pub normalized: bool,
+    pub input_scale: Option<f32>,
+    pub input_zero_point: Option<i32>,
+    pub output_quant: Vec<QuantParams>,
     pub batch: Batch,
     pub image_size: (Option<u32>, Option<u32>),
 }
@@ -87,119 +298,434 @@ pub struct OrtConfig {
 #[derive(Debug)]
 pub struct OrtBackend {
     // ORT engine
-    session: Session,
+    sessions: Vec<Session>,
+    split: bool,
     task: YOLOTask,
+    family: ModelFamily,
+    end_to_end: Option<EndToEnd>,
     ep: OrtEP,
     batch: Batch,
     inputs: OrtInputs,
+    normalized: bool,
+    input_quant: Option<QuantParams>,
+    output_quant: Vec<QuantParams>,
+    buffers: Mutex<InputBuffers>,
+}
+
+#[derive(Debug, Default)]
+struct InputBuffers {
+    // model inputs converted from f32, kept across batches
+    f32: Array<f32, IxDyn>,
+    f16: Array<f16, IxDyn>,
+    u8: Array<u8, IxDyn>,
+    i8: Array<i8, IxDyn>,
 }
 
 impl OrtBackend {
-    pub fn build(args: OrtConfig) -> Result<Self> {
-        // build env & session
-        let env = Environment::builder()
-            .with_name("YOLOv8")
-            .with_log_level(ort::LoggingLevel::Verbose)
-            .build()?
-            .into_arc();
-        let session = SessionBuilder::new(&env)?.with_model_from_file(&args.f)?;
+    pub fn build(mut args: OrtConfig) -> Result<Self, LoadError> {
+        let bytes = match args.bytes.take() {
+            Some(bytes) => bytes,
+            None => Self::read_model(&mut args)?,
+        };
+        Self::build_from_memory(&bytes, args)
+    }
 
-        // get inputs
-        let mut inputs = OrtInputs::new(&session);
+    fn read_model(args: &mut OrtConfig) -> Result<Arc<[u8]>, LoadError> {
+        // an optimized model that was saved before is used in place of the original, without
+        // optimizing it again. Otherwise `args.optimized` stays set, and the first session saves it.
+        if let Some(optimized) = args.optimized.clone() {
+            if std::path::Path::new(&optimized).is_file() {
+                info!("Using optimized model {optimized}.");
+                args.f = optimized;
+                args.optimized = None;
+                args.cpu.opt_level = OptLevel::Disable;
+            } else {
+                info!(
+                    "Optimized model {optimized} not found, optimizing {} and saving it there.",
+                    args.f
+                );
+            }
+        }
+        if !std::path::Path::new(&args.f).is_file() {
+            return Err(LoadError::NotFound(args.f.clone()));
+        }
+        std::fs::read(&args.f)
+            .map(Arc::from)
+            .map_err(|e| LoadError::Read(args.f.clone(), e))
+    }
+
+    /*
+    Builds the engine from the bytes of an ONNX model, e.g. a bundled resource
+    or a decrypted blob; `args.f` only names it in the logs. The inputs are read
+    from the bytes first, so only the one session with the execution provider
+    is built. Models with external data have to be loaded from a file.
+    */
+    pub fn build_from_memory(model: &[u8], args: OrtConfig) -> Result<Self, LoadError> {
+        // get inputs, the first is a batch of images: [batch, channels, height, width]
+        let mut inputs = OrtInputs::new(model)?;
+        let shape = match inputs.shapes.first() {
+            Some(shape) if shape.len() == 4 => shape.clone(),
+            Some(shape) => {
+                return Err(LoadError::Onnx(format!(
+                    "input `{}` has shape {shape:?}, expected [batch, channels, height, width]",
+                    inputs.names[0]
+                )))
+            }
+            None => return Err(LoadError::Onnx("the model has no inputs".to_string())),
+        };
 
         // batch size
         let mut batch = args.batch;
-        let batch = if inputs.shapes[0][0] == -1 {
+        let batch = if shape[0] == -1 {
             batch
         } else {
-            assert_eq!(
-                inputs.shapes[0][0] as u32, batch.opt,
-                "Expected batch size: {}, got {}. Try using `--batch {}`.",
-                inputs.shapes[0][0] as u32, batch.opt, inputs.shapes[0][0] as u32
-            );
-            batch.opt = inputs.shapes[0][0] as u32;
+            if shape[0] as u32 != batch.opt {
+                return Err(LoadError::Batch {
+                    expected: shape[0] as u32,
+                    got: batch.opt,
+                });
+            }
+            batch.opt = shape[0] as u32;
             batch
         };
 
         // input size: height and width
-        let height = if inputs.shapes[0][2] == -1 {
+        let height = if shape[2] == -1 {
             match args.image_size.0 {
                 Some(height) => height,
-                None => panic!("Failed to get model height. Make it explicit with `--height`"),
+                None => return Err(LoadError::InputSize("height")),
             }
         } else {
-            inputs.shapes[0][2] as u32
+            shape[2] as u32
         };
-        let width = if inputs.shapes[0][3] == -1 {
+        let width = if shape[3] == -1 {
             match args.image_size.1 {
                 Some(width) => width,
-                None => panic!("Failed to get model width. Make it explicit with `--width`"),
+                None => return Err(LoadError::InputSize("width")),
             }
         } else {
-            inputs.shapes[0][3] as u32
+            shape[3] as u32
         };
         inputs.sizes.push(vec![height, width]);
 
+        // dtype, integer inputs are quantized pixels unless told otherwise:
+        // uint8 as 0..255 and int8 as -128..127
+        let pixel_scale = if args.normalized { 1. / 255. } else { 1. };
+        let input_quant = match inputs.dtypes[0] {
+            TensorElementDataType::Float32 | TensorElementDataType::Float16 => None,
+            TensorElementDataType::Uint8 => Some(QuantParams {
+                scale: args.input_scale.unwrap_or(pixel_scale),
+                zero_point: args.input_zero_point.unwrap_or(0),
+            }),
+            TensorElementDataType::Int8 => Some(QuantParams {
+                scale: args.input_scale.unwrap_or(pixel_scale),
+                zero_point: args.input_zero_point.unwrap_or(-128),
+            }),
+            dtype => return Err(LoadError::Dtype(format!("{:?}", dtype))),
+        };
+
         // build provider
         let (ep, provider) = match args.ep {
             OrtEP::Cuda(device_id) => Self::set_ep_cuda(device_id),
-            OrtEP::Trt(device_id) => Self::set_ep_trt(device_id, args.trt_fp16, &batch, &inputs),
-            _ => (OrtEP::Cpu, ExecutionProvider::CPU(Default::default())),
+            OrtEP::Trt(device_id) => Self::set_ep_trt(device_id, args.trt_fp16, &batch, &inputs)?,
+            _ => (
+                OrtEP::Cpu,
+                ExecutionProvider::CPU(CPUExecutionProviderOptions {
+                    use_arena: args.cpu.mem_arena,
+                }),
+            ),
         };
 
-        // build session again with the new provider
-        let session = SessionBuilder::new(&env)?
-            // .with_optimization_level(ort::GraphOptimizationLevel::Level3)?
-            .with_execution_providers([provider])?
-            .with_model_from_file(args.f)?;
+        // build env & session with the provider and session options
+        let env = Environment::builder()
+            .with_name("YOLOv8")
+            .with_log_level(ort::LoggingLevel::Verbose)
+            .build()?
+            .into_arc();
+        // a pool only pays off when a batch is split across it, which takes a dynamic batch.
+        // Otherwise every batch would run on one session with a part of the cores.
+        let mut size = args.pool.size.max(1);
+        if size > 1 && (shape[0] != -1 || batch.opt <= 1 || args.pool.split == Some(false)) {
+            warn!("Session pool: batches of this model are not split, using a single session.");
+            size = 1;
+        }
+        // a pool shares the environment, every session gets a part of the cores
+        let mut cpu = args.cpu.clone();
+        let cores = std::thread::available_parallelism().map_or(4, |n| n.get());
+        if size > 1 {
+            cpu.intra_threads = Some(args.pool.threads.unwrap_or((cores / size).max(1) as i16));
+        }
+        let mut sessions = vec![];
+        for i in 0..size {
+            let mut builder = Self::session_builder(&env, &cpu)?;
+            // ORT writes the graph as optimized for this execution provider and level
+            if let (0, Some(path)) = (i, &args.optimized) {
+                if let Some(dir) = std::path::Path::new(path).parent() {
+                    std::fs::create_dir_all(dir).map_err(|e| LoadError::Read(path.clone(), e))?;
+                }
+                builder = builder.with_optimized_model_path(path)?;
+            }
+            sessions.push(
+                builder
+                    .with_execution_providers([provider.clone()])?
+                    .with_model_from_memory(model)?,
+            );
+        }
+        if let Some(path) = &args.optimized {
+            info!("Saved the optimized model to {path}.");
+        }
+        info!(
+            "Loaded {} ({} bytes), {size} session(s), {:?} threads each.",
+            args.f,
+            model.len(),
+            cpu.intra_threads
+        );
 
         // task: using given one or guessing
         let task = match args.task {
             Some(task) => task,
-            None => match session.metadata() {
-                Err(_) => panic!("No metadata found. Try making it explicit by `--task`"),
+            None => match sessions[0].metadata() {
+                Err(_) => return Err(LoadError::Task("no metadata found".to_string())),
                 Ok(metadata) => match metadata.custom("task") {
-                    Err(_) => panic!("Can not get custom value. Try making it explicit by `--task`"),
+                    Err(e) => return Err(LoadError::Task(format!("can not read metadata, {e}"))),
                     Ok(value) => match value {
-                        None => panic!("No corresponding value of `task` found in metadata. Make it explicit by `--task`"),
+                        None => return Err(LoadError::Task("no `task` in metadata".to_string())),
                         Some(task) => match task.as_str() {
                             "classify" => YOLOTask::Classify,
                             "detect" => YOLOTask::Detect,
                             "pose" => YOLOTask::Pose,
                             "segment" => YOLOTask::Segment,
-                            x => todo!("{:?} is not supported for now!", x),
+                            "obb" => YOLOTask::Obb,
+                            x => return Err(LoadError::Task(format!("`{x}` is not supported"))),
                         },
                     },
                 },
             },
         };
 
-        Ok(Self {
-            session,
+        let mut engine = Self {
+            sessions,
+            split: false,
             task,
+            family: ModelFamily::default(),
+            end_to_end: None,
             ep,
             batch,
             inputs,
-        })
+            normalized: args.normalized,
+            input_quant,
+            output_quant: args.output_quant,
+            buffers: Mutex::new(InputBuffers::default()),
+        };
+
+        // outputs of NMS inside the graph, decoded as they are
+        engine.end_to_end = engine.find_end_to_end();
+        if let Some(end_to_end) = &engine.end_to_end {
+            info!("End-to-end model with NMS in the graph: {end_to_end:?}.");
+        }
+
+        // family: using given one or guessing
+        engine.family = match args.family {
+            Some(family) => family,
+            None => {
+                let family = engine.guess_family();
+                info!("Guessed model family: {family:?}.");
+                family
+            }
+        };
+
+        // batches split across the pool, unless whole batches on one session are faster
+        if engine.sessions.len() > 1 {
+            engine.split = args.pool.split.unwrap_or_else(|| engine.faster_to_split());
+            if !engine.split {
+                // the first session is kept as it is, with its share of the threads
+                info!(
+                    "Session pool: whole batches are faster, using a single session. \
+                     Set `pool_size = 1` to give it all the threads."
+                );
+                engine.sessions.truncate(1);
+                return Ok(engine);
+            }
+            info!(
+                "Session pool splits batches across {} sessions.",
+                engine.sessions.len()
+            );
+        }
+        Ok(engine)
     }
 
-    pub fn fetch_inputs_from_session(
-        session: &Session,
-    ) -> (Vec<Vec<i32>>, Vec<TensorElementDataType>, Vec<String>) {
-        // get inputs attrs from ONNX model
-        let mut shapes = Vec::new();
-        let mut dtypes = Vec::new();
-        let mut names = Vec::new();
-        for i in session.inputs.iter() {
-            let shape: Vec<i32> = i
-                .dimensions()
-                .map(|x| if let Some(x) = x { x as i32 } else { -1i32 })
-                .collect();
-            shapes.push(shape);
-            dtypes.push(i.input_type);
-            names.push(i.name.clone());
+    fn faster_to_split(&mut self) -> bool {
+        // a few synthetic batches each way, the first of each not counted
+        let xs = Array::zeros(IxDyn(&[
+            self.batch() as usize,
+            3,
+            self.height() as usize,
+            self.width() as usize,
+        ]));
+        let mut ys = vec![];
+        let mut times = [Duration::ZERO; 2];
+        for (i, split) in [false, true].into_iter().enumerate() {
+            self.split = split;
+            for n in 0..=SPLIT_TIMING_RUNS {
+                let t = Instant::now();
+                if let Err(e) = self.run_into(xs.view(), &mut ys, false) {
+                    warn!("Session pool: timing failed, not splitting batches: {e}");
+                    return false;
+                }
+                if n > 0 {
+                    times[i] += t.elapsed();
+                }
+            }
+        }
+        info!(
+            "Session pool: {:?} per whole batch, {:?} per split batch.",
+            times[0] / SPLIT_TIMING_RUNS,
+            times[1] / SPLIT_TIMING_RUNS
+        );
+        times[1] < times[0]
+    }
+
+    fn session(&self) -> &Session {
+        // inputs, outputs and metadata are the same for every session of the pool
+        &self.sessions[0]
+    }
+
+    fn find_end_to_end(&self) -> Option<EndToEnd> {
+        let names: Vec<String> = self
+            .session()
+            .outputs
+            .iter()
+            .map(|output| output.name.clone())
+            .collect();
+        EndToEnd::find(&names, &self.output_shapes())
+    }
+
+    fn guess_family(&self) -> ModelFamily {
+        ModelFamily::guess(
+            self.fetch_from_metadata("description").as_deref(),
+            &self.task,
+            &self.output_shapes()[0],
+        )
+    }
+
+    fn session_builder(
+        env: &Arc<Environment>,
+        cpu: &CpuOptions,
+    ) -> Result<SessionBuilder, LoadError> {
+        let mut builder = SessionBuilder::new(env)?
+            .with_optimization_level(cpu.opt_level.level())?
+            .with_parallel_execution(cpu.parallel)?
+            .with_memory_pattern(cpu.mem_pattern)?
+            .with_allocator(if cpu.mem_arena {
+                AllocatorType::Arena
+            } else {
+                AllocatorType::Device
+            })?;
+        if let Some(n) = cpu.intra_threads {
+            builder = builder.with_intra_threads(n)?;
+        }
+        if let Some(n) = cpu.inter_threads {
+            builder = builder.with_inter_threads(n)?;
+        }
+        Ok(builder)
+    }
+
+    /*
+    Builds the model with a few combinations of thread counts, graph optimization
+    level, execution mode and memory arena, times `iterations` synthetic batches
+    with each and returns the fastest. Meant to be run once on a new machine,
+    the result can then go into the args toml.
+    */
+    pub fn autotune(args: &OrtConfig, iterations: u32) -> Result<CpuOptions, LoadError> {
+        // the model is read once and shared by every candidate
+        let mut args = args.clone();
+        // a saved optimized model is not optimized again, so the level makes no difference
+        let mut opt_levels = vec![OptLevel::Extended, OptLevel::All];
+        if args.bytes.is_none() {
+            let saved = args.optimized.clone();
+            args.bytes = Some(Self::read_model(&mut args)?);
+            if saved.is_some() && args.optimized.is_none() {
+                opt_levels = vec![OptLevel::Disable];
+            }
+        }
+
+        let cores = std::thread::available_parallelism().map_or(4, |n| n.get()) as i16;
+        let mut threads = vec![cores, (cores / 2).max(1), (cores / 4).max(1)];
+        threads.dedup();
+
+        let mut candidates = vec![];
+        for &intra in threads.iter() {
+            for (parallel, inter) in [(false, None), (true, Some(2))] {
+                for &opt_level in opt_levels.iter() {
+                    for mem_arena in [true, false] {
+                        candidates.push(CpuOptions {
+                            intra_threads: Some(intra),
+                            inter_threads: inter,
+                            opt_level,
+                            parallel,
+                            mem_arena,
+                            mem_pattern: args.cpu.mem_pattern,
+                        });
+                    }
+                }
+            }
+        }
+
+        let mut best: Option<(CpuOptions, Duration)> = None;
+        for cpu in candidates.into_iter() {
+            // only the model that is kept saves the optimized model
+            let engine = match Self::build(OrtConfig {
+                cpu: cpu.clone(),
+                pool: PoolOptions::default(),
+                optimized: None,
+                ..args.clone()
+            }) {
+                Ok(engine) => engine,
+                Err(e) => {
+                    warn!("Auto-tune: {cpu:?} failed to build: {e}");
+                    continue;
+                }
+            };
+            let xs = Array::zeros(IxDyn(&[
+                engine.batch() as usize,
+                3,
+                engine.height() as usize,
+                engine.width() as usize,
+            ]));
+
+            // the first run pays for initialization, the median of the rest is the score
+            let _ = engine.run(xs.view(), false);
+            let mut times = vec![];
+            for _ in 0..iterations.max(1) {
+                let t = Instant::now();
+                if let Err(e) = engine.run(xs.view(), false) {
+                    warn!("Auto-tune: {cpu:?} failed: {e}");
+                    break;
+                }
+                times.push(t.elapsed());
+            }
+            if times.is_empty() {
+                continue;
+            }
+            times.sort();
+            let median = times[times.len() / 2];
+            info!("Auto-tune: {cpu:?}: {median:?}");
+            let faster = match &best {
+                Some((_, t)) => median < *t,
+                None => true,
+            };
+            if faster {
+                best = Some((cpu, median));
+            }
+        }
+
+        match best {
+            Some((cpu, median)) => {
+                info!("Auto-tune picked {cpu:?} ({median:?} per batch).");
+                Ok(cpu)
+            }
+            None => Ok(args.cpu.clone()),
         }
-        (shapes, dtypes, names)
     }
 
     pub fn set_ep_cuda(device_id: u32) -> (OrtEP, ExecutionProvider) {
@@ -223,17 +749,14 @@ impl OrtBackend {
         fp16: bool,
         batch: &Batch,
         inputs: &OrtInputs,
-    ) -> (OrtEP, ExecutionProvider) {
+    ) -> Result<(OrtEP, ExecutionProvider), LoadError> {
         // set TensorRT
         if ExecutionProvider::TensorRT(Default::default()).is_available() {
             let (height, width) = (inputs.sizes[0][0], inputs.sizes[0][1]);
 
             // dtype match checking
             if inputs.dtypes[0] == TensorElementDataType::Float16 && !fp16 {
-                panic!(
-                    "Dtype mismatch! Expected: Float32, got: {:?}. You should use `--fp16`",
-                    inputs.dtypes[0]
-                );
+                return Err(LoadError::Fp16(format!("{:?}", inputs.dtypes[0])));
             }
 
             // dynamic shape: input_tensor_1:dim_1xdim_2x...,input_tensor_2:dim_3xdim_4x...,...
@@ -251,7 +774,7 @@ impl OrtBackend {
             let _ = opt_string.pop();
             let _ = min_string.pop();
             let _ = max_string.pop();
-            (
+            Ok((
                 OrtEP::Trt(device_id),
                 ExecutionProvider::TensorRT(TensorRTExecutionProviderOptions {
                     device_id,
@@ -262,16 +785,16 @@ impl OrtBackend {
                     profile_opt_shapes: opt_string,
                     ..Default::default()
                 }),
-            )
+            ))
         } else {
             println!("> TensorRT is not available! Try using CUDA...");
-            Self::set_ep_cuda(device_id)
+            Ok(Self::set_ep_cuda(device_id))
         }
     }
 
     pub fn fetch_from_metadata(&self, key: &str) -> Option<String> {
         // fetch value from onnx model file by key
-        match self.session.metadata() {
+        match self.session().metadata() {
             Err(_) => None,
             Ok(metadata) => match metadata.custom(key) {
                 Err(_) => None,
@@ -280,93 +803,245 @@ impl OrtBackend {
         }
     }
 
-    pub fn run(&self, xs: Array<f32, IxDyn>, profile: bool) -> Result<Vec<Array<f32, IxDyn>>> {
-        // ORT inference
-        match self.dtype() {
-            TensorElementDataType::Float16 => self.run_fp16(xs, profile),
-            TensorElementDataType::Float32 => self.run_fp32(xs, profile),
-            _ => todo!(),
+    fn fetch_yaml_from_metadata(&self, key: &str) -> Option<Yaml> {
+        // metadata values are Python literals, see `metadata::parse`
+        let value = self.fetch_from_metadata(key)?;
+        let yaml = metadata::parse(&value);
+        if yaml.is_none() {
+            warn!("Failed to parse `{key}` in the model metadata: {value}");
         }
+        yaml
+    }
+
+    pub fn run(&self, xs: ArrayView<f32, IxDyn>, profile: bool) -> Result<Vec<Array<f32, IxDyn>>> {
+        let mut ys = vec![];
+        self.run_into(xs, &mut ys, profile)?;
+        Ok(ys
+            .into_iter()
+            .map(|y| Arc::try_unwrap(y).unwrap_or_else(|y| (*y).clone()))
+            .collect())
     }
 
-    pub fn run_fp16(&self, xs: Array<f32, IxDyn>, profile: bool) -> Result<Vec<Array<f32, IxDyn>>> {
-        // f32->f16
+    /*
+    ORT inference, `xs` are pixels in [0, 1] and converted to what the model
+    expects. The conversion goes into buffers kept across batches, and the
+    outputs are written into `ys` in place unless their shape changed or the
+    previous results still hold them. ort 1.16 has no I/O binding to host
+    memory, so the session allocates its outputs and they are copied out.
+    */
+    pub fn run_into(
+        &self,
+        xs: ArrayView<f32, IxDyn>,
+        ys: &mut Vec<Arc<Array<f32, IxDyn>>>,
+        profile: bool,
+    ) -> Result<()> {
         let t = std::time::Instant::now();
-        let xs = xs.mapv(f16::from_f32);
-        if profile {
-            println!("[ORT f32->f16]: {:?}", t.elapsed());
+        let scale = if self.normalized { 1. } else { 255. };
+        // a concurrent run converts into buffers of its own
+        let mut local = InputBuffers::default();
+        let mut shared = self.buffers.try_lock();
+        let buffers = match shared.as_deref_mut() {
+            Ok(buffers) => buffers,
+            Err(_) => &mut local,
+        };
+        match (self.dtype(), self.input_quant) {
+            (TensorElementDataType::Float32, _) if self.normalized => {
+                self.run_typed(xs, ys, t, profile)
+            }
+            (TensorElementDataType::Float32, _) => {
+                let xs = Self::convert(&mut buffers.f32, &xs, |x| x * scale);
+                self.run_typed(xs, ys, t, profile)
+            }
+            (TensorElementDataType::Float16, _) => {
+                let xs = Self::convert(&mut buffers.f16, &xs, |x| f16::from_f32(x * scale));
+                self.run_typed(xs, ys, t, profile)
+            }
+            (TensorElementDataType::Uint8, Some(q)) => {
+                let xs = Self::convert(&mut buffers.u8, &xs, |x| {
+                    q.quantize(x * scale, 0, 255) as u8
+                });
+                self.run_typed(xs, ys, t, profile)
+            }
+            (TensorElementDataType::Int8, Some(q)) => {
+                let xs = Self::convert(&mut buffers.i8, &xs, |x| {
+                    q.quantize(x * scale, -128, 127) as i8
+                });
+                self.run_typed(xs, ys, t, profile)
+            }
+            (dtype, _) => Err(LoadError::Dtype(format!("{:?}", dtype)).into()),
         }
+    }
 
-        // h2d
-        let t = std::time::Instant::now();
-        let xs = CowArray::from(xs);
-        let xs = vec![Value::from_array(self.session.allocator(), &xs)?];
-        if profile {
-            println!("[ORT H2D]: {:?}", t.elapsed());
+    fn convert<'a, T>(
+        buffer: &'a mut Array<T, IxDyn>,
+        xs: &ArrayView<f32, IxDyn>,
+        f: impl Fn(f32) -> T + Sync + Send,
+    ) -> ArrayView<'a, T, IxDyn>
+    where
+        T: Clone + Default + Send,
+    {
+        if buffer.shape() != xs.shape() {
+            *buffer = Array::default(xs.raw_dim());
         }
+        Zip::from(&mut *buffer)
+            .and(xs)
+            .par_for_each(|y, &x| *y = f(x));
+        buffer.view()
+    }
 
-        // run
+    fn run_typed<T>(
+        &self,
+        xs: ArrayView<T, IxDyn>,
+        ys: &mut Vec<Arc<Array<f32, IxDyn>>>,
+        t: Instant,
+        profile: bool,
+    ) -> Result<()>
+    where
+        T: IntoTensorElementDataType + std::fmt::Debug + Clone + Sync + 'static,
+    {
+        if profile && self.dtype() != TensorElementDataType::Float32 {
+            println!("[ORT f32->{:?}]: {:?}", self.dtype(), t.elapsed());
+        }
+
+        let n = xs.shape()[0];
+        let parts = if self.split {
+            self.sessions.len().min(n)
+        } else {
+            1
+        };
+        if parts <= 1 {
+            return self.run_session(0, xs, ys, profile);
+        }
+
+        // one chunk of the batch per session, all at the same time
         let t = std::time::Instant::now();
-        let ys = self.session.run(xs)?;
+        let chunks: Vec<Result<Vec<Arc<Array<f32, IxDyn>>>>> = std::thread::scope(|scope| {
+            let handles: Vec<_> = xs
+                .axis_chunks_iter(Axis(0), n.div_ceil(parts))
+                .enumerate()
+                .map(|(i, xs)| {
+                    scope.spawn(move || {
+                        let mut ys = vec![];
+                        self.run_session(i, xs, &mut ys, false).map(|_| ys)
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("session thread panicked"))
+                .collect()
+        });
+        let chunks = chunks.into_iter().collect::<Result<Vec<_>>>()?;
         if profile {
-            println!("[ORT Inference]: {:?}", t.elapsed());
+            println!("[ORT Pool, {parts} sessions]: {:?}", t.elapsed());
         }
 
-        // d2h
-        Ok(ys
-            .iter()
-            .map(|x| {
-                // d2h
-                let t = std::time::Instant::now();
-                let x = x.try_extract::<_>().unwrap().view().clone().into_owned();
-                if profile {
-                    println!("[ORT D2H]: {:?}", t.elapsed());
-                }
-
-                // f16->f32
-                let t_ = std::time::Instant::now();
-                let x = x.mapv(f16::to_f32);
-                if profile {
-                    println!("[ORT f16->f32]: {:?}", t_.elapsed());
-                }
-                x
-            })
-            .collect::<Vec<Array<_, _>>>())
+        // concatenated along the batch, into `ys` where it can be reused
+        ys.truncate(chunks[0].len());
+        ys.resize_with(chunks[0].len(), Default::default);
+        for (i, y) in ys.iter_mut().enumerate() {
+            let mut shape = chunks[0][i].shape().to_vec();
+            shape[0] = chunks.iter().map(|chunk| chunk[i].shape()[0]).sum();
+            let reusable = match Arc::get_mut(y) {
+                Some(y) => y.shape() == shape,
+                None => false,
+            };
+            if !reusable {
+                *y = Arc::new(Array::zeros(IxDyn(&shape)));
+            }
+            let y = Arc::get_mut(y).expect("a fresh or unshared output");
+            let mut offset = 0;
+            for chunk in chunks.iter() {
+                let len = chunk[i].shape()[0];
+                y.slice_axis_mut(Axis(0), Slice::from(offset..offset + len))
+                    .assign(&chunk[i]);
+                offset += len;
+            }
+        }
+        Ok(())
     }
 
-    pub fn run_fp32(&self, xs: Array<f32, IxDyn>, profile: bool) -> Result<Vec<Array<f32, IxDyn>>> {
-        // h2d
+    fn run_session<T>(
+        &self,
+        i: usize,
+        xs: ArrayView<T, IxDyn>,
+        ys: &mut Vec<Arc<Array<f32, IxDyn>>>,
+        profile: bool,
+    ) -> Result<()>
+    where
+        T: IntoTensorElementDataType + std::fmt::Debug + Clone + 'static,
+    {
+        let session = &self.sessions[i];
+
+        // h2d, the tensor borrows `xs`
         let t = std::time::Instant::now();
-        let xs = CowArray::from(xs);
-        let xs = vec![Value::from_array(self.session.allocator(), &xs)?];
+        let xs = CowArray::from(xs.view());
+        let xs = vec![Value::from_array(session.allocator(), &xs)?];
         if profile {
             println!("[ORT H2D]: {:?}", t.elapsed());
         }
 
         // run
         let t = std::time::Instant::now();
-        let ys = self.session.run(xs)?;
+        let outputs = session.run(xs)?;
         if profile {
             println!("[ORT Inference]: {:?}", t.elapsed());
         }
 
-        // d2h
-        Ok(ys
-            .iter()
-            .map(|x| {
-                let t = std::time::Instant::now();
-                let x = x.try_extract::<_>().unwrap().view().clone().into_owned();
-                if profile {
-                    println!("[ORT D2H]: {:?}", t.elapsed());
-                }
-                x
-            })
-            .collect::<Vec<Array<_, _>>>())
+        // d2h, every output back to f32
+        ys.truncate(outputs.len());
+        ys.resize_with(outputs.len(), Default::default);
+        for (i, (x, y)) in outputs.iter().zip(ys.iter_mut()).enumerate() {
+            let t = std::time::Instant::now();
+            let quant = self.output_quant.get(i).copied();
+            match self.session().outputs[i].output_type {
+                TensorElementDataType::Float32 => Self::extract::<f32>(x, y, |&v| v)?,
+                TensorElementDataType::Float16 => Self::extract::<f16>(x, y, |v| v.to_f32())?,
+                TensorElementDataType::Float64 => Self::extract::<f64>(x, y, |&v| v as f32)?,
+                TensorElementDataType::Uint8 => Self::extract::<u8>(x, y, |&v| {
+                    quant.map_or(v as f32, |q| q.dequantize(v as i32))
+                })?,
+                TensorElementDataType::Int8 => Self::extract::<i8>(x, y, |&v| {
+                    quant.map_or(v as f32, |q| q.dequantize(v as i32))
+                })?,
+                TensorElementDataType::Int32 => Self::extract::<i32>(x, y, |&v| v as f32)?,
+                TensorElementDataType::Int64 => Self::extract::<i64>(x, y, |&v| v as f32)?,
+                dtype => return Err(LoadError::Dtype(format!("{:?}", dtype)).into()),
+            }
+            if profile {
+                println!("[ORT D2H]: {:?}", t.elapsed());
+            }
+        }
+        Ok(())
+    }
+
+    fn extract<T>(
+        x: &Value,
+        y: &mut Arc<Array<f32, IxDyn>>,
+        f: impl Fn(&T) -> f32 + Sync + Send,
+    ) -> Result<()>
+    where
+        T: IntoTensorElementDataType + std::fmt::Debug + Clone + Sync,
+    {
+        let x = x.try_extract::<T>()?;
+        let x = x.view();
+
+        // a new array if the shape changed or the last results still hold this one
+        let reusable = match Arc::get_mut(y) {
+            Some(y) => y.shape() == x.shape(),
+            None => false,
+        };
+        if !reusable {
+            *y = Arc::new(Array::zeros(x.raw_dim()));
+        }
+        let y = Arc::get_mut(y).expect("a fresh or unshared output");
+        Zip::from(y).and(&x).par_for_each(|y, x| *y = f(x));
+        Ok(())
     }
 
     pub fn output_shapes(&self) -> Vec<Vec<i32>> {
         let mut shapes = Vec::new();
-        for o in &self.session.outputs {
+        for o in &self.session().outputs {
             let shape: Vec<_> = o
                 .dimensions()
                 .map(|x| if let Some(x) = x { x as i32 } else { -1i32 })
@@ -378,7 +1053,7 @@ impl OrtBackend {
 
     pub fn output_dtypes(&self) -> Vec<TensorElementDataType> {
         let mut dtypes = Vec::new();
-        self.session
+        self.session()
             .outputs
             .iter()
             .for_each(|x| dtypes.push(x.output_type));
@@ -429,6 +1104,23 @@ impl OrtBackend {
         &self.ep
     }
 
+    pub fn family(&self) -> ModelFamily {
+        self.family
+    }
+
+    pub fn end_to_end(&self) -> Option<&EndToEnd> {
+        self.end_to_end.as_ref()
+    }
+
+    pub fn anchor_axis(&self) -> usize {
+        // axis of the anchors (or rows) in the first output
+        if self.family.transposed() {
+            1
+        } else {
+            2
+        }
+    }
+
     pub fn task(&self) -> YOLOTask {
         self.task.clone()
     }
@@ -436,29 +1128,17 @@ impl OrtBackend {
     pub fn names(&self) -> Option<Vec<String>> {
         // class names, metadata parsing
         // String format: `{0: 'person', 1: 'bicycle', 2: 'sports ball', ..., 27: "yellow_lady's_slipper"}`
-        match self.fetch_from_metadata("names") {
-            Some(names) => {
-                let re = Regex::new(r#"(['"])([-()\w '"]+)(['"])"#).unwrap();
-                let mut names_ = vec![];
-                for (_, [_, name, _]) in re.captures_iter(&names).map(|x| x.extract()) {
-                    names_.push(name.to_string());
-                }
-                Some(names_)
-            }
-            None => None,
-        }
+        metadata::names(&self.fetch_yaml_from_metadata("names")?)
     }
 
     pub fn nk(&self) -> Option<u32> {
         // num_keypoints, metadata parsing: String `nk` in onnx model: `[17, 3]`
-        match self.fetch_from_metadata("kpt_shape") {
-            None => None,
-            Some(kpt_string) => {
-                let re = Regex::new(r"([0-9]+), ([0-9]+)").unwrap();
-                let caps = re.captures(&kpt_string).unwrap();
-                Some(caps.get(1).unwrap().as_str().parse::<u32>().unwrap())
-            }
-        }
+        metadata::kpt_shape(&self.fetch_yaml_from_metadata("kpt_shape")?).map(|(nk, _)| nk)
+    }
+
+    pub fn skeleton(&self) -> Option<Vec<(usize, usize)>> {
+        // skeleton edges, metadata parsing: String `skeleton` in onnx model: `[[15, 13], [13, 11], ...]`
+        metadata::skeleton(&self.fetch_yaml_from_metadata("skeleton")?)
     }
 
     pub fn nc(&self) -> Option<u32> {
@@ -466,17 +1146,26 @@ impl OrtBackend {
         match self.names() {
             // by names
             Some(names) => Some(names.len() as u32),
+            // end-to-end outputs do not tell
+            None if self.end_to_end.is_some() => None,
             None => match self.task() {
                 // by task calculation
                 YOLOTask::Classify => Some(self.output_shapes()[0][1] as u32),
-                YOLOTask::Detect => {
-                    if self.output_shapes()[0][1] == -1 {
-                        None
-                    } else {
-                        // cxywhclss
+                YOLOTask::Detect => match self.family {
+                    // x1y1x2y2 score class
+                    ModelFamily::Yolov10 => None,
+                    // cxywh obj clss
+                    ModelFamily::Yolov5 if self.output_shapes()[0][2] != -1 => {
+                        Some(self.output_shapes()[0][2] as u32 - 5)
+                    }
+                    // cxywhclss
+                    ModelFamily::Yolov8 | ModelFamily::Yolo11
+                        if self.output_shapes()[0][1] != -1 =>
+                    {
                         Some(self.output_shapes()[0][1] as u32 - 4)
                     }
-                }
+                    _ => None,
+                },
                 YOLOTask::Pose => {
                     match self.nk() {
                         None => None,
@@ -491,13 +1180,31 @@ impl OrtBackend {
                     }
                 }
                 YOLOTask::Segment => {
-                    if self.output_shapes()[0][1] == -1 {
+                    if self.family.objectness() {
+                        if self.output_shapes()[0][2] == -1 {
+                            None
+                        } else {
+                            // cxywh obj clss nm
+                            Some(
+                                (self.output_shapes()[0][2] - self.output_shapes()[1][1]) as u32
+                                    - 5,
+                            )
+                        }
+                    } else if self.output_shapes()[0][1] == -1 {
                         None
                     } else {
                         // cxywhclssnm
                         Some((self.output_shapes()[0][1] - self.output_shapes()[1][1]) as u32 - 4)
                     }
                 }
+                YOLOTask::Obb => {
+                    if self.output_shapes()[0][1] == -1 {
+                        None
+                    } else {
+                        // cxywhclssr
+                        Some(self.output_shapes()[0][1] as u32 - 4 - 1)
+                    }
+                }
             },
         }
     }
@@ -512,12 +1219,16 @@ impl OrtBackend {
 
     pub fn na(&self) -> Option<u32> {
         // num_anchors
+        if self.end_to_end.is_some() {
+            return None;
+        }
         match self.task() {
-            YOLOTask::Segment | YOLOTask::Detect | YOLOTask::Pose => {
-                if self.output_shapes()[0][2] == -1 {
+            YOLOTask::Segment | YOLOTask::Detect | YOLOTask::Pose | YOLOTask::Obb => {
+                let axis = self.anchor_axis();
+                if self.output_shapes()[0][axis] == -1 {
                     None
                 } else {
-                    Some(self.output_shapes()[0][2] as u32)
+                    Some(self.output_shapes()[0][axis] as u32)
                 }
             }
             _ => None,
@@ -0,0 +1,207 @@
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+
+use crate::{Bbox, Embedding, Masks, Obb, Point2, Rle, YOLOResult};
+
+// version of the serialized results, bumped when a field changes meaning or goes away
+pub const RECORD_VERSION: u32 = 1;
+
+// classes listed with a classification result
+const TOP_CLASSES: usize = 5;
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum MaskEncoding {
+    // how segmentation masks are written, decoding them is not free
+    None,
+    #[default]
+    Rle,
+    Polygons,
+}
+
+/*
+The serialized form of a `YOLOResult`, which is what goes to the frontend,
+logs and files. Every detection carries its class name next to the id, and
+masks are written in the requested encoding. Readers check `version` and
+refuse records of a newer one; fields added later get serde defaults.
+*/
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ResultRecord {
+    pub version: u32,
+    pub model_id: Option<String>,
+    pub probs: Option<Embedding>,
+    #[serde(default)]
+    pub classes: Vec<ClassRecord>,
+    #[serde(default)]
+    pub detections: Vec<DetectionRecord>,
+    #[serde(default)]
+    pub obbs: Vec<ObbRecord>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ClassRecord {
+    // one of the top classes of a classification result
+    pub id: usize,
+    pub name: String,
+    pub confidence: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct DetectionRecord {
+    pub name: String,
+    pub bbox: Bbox,
+    pub keypoints: Option<Vec<Point2>>,
+    pub mask: Option<MaskRecord>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MaskRecord {
+    // full-image RLE, or polygons in image coordinates
+    Rle(Rle),
+    Polygons(Vec<Vec<Point2>>),
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ObbRecord {
+    pub name: String,
+    pub obb: Obb,
+}
+
+fn class_name(names: &[String], id: usize) -> String {
+    names.get(id).cloned().unwrap_or_else(|| id.to_string())
+}
+
+impl YOLOResult {
+    pub fn to_record(&self, names: &[String], masks: MaskEncoding) -> ResultRecord {
+        let masks: Option<Vec<MaskRecord>> = match (masks, self.masks()) {
+            (MaskEncoding::Rle, Some(m)) => {
+                Some(m.rle().into_iter().map(MaskRecord::Rle).collect())
+            }
+            (MaskEncoding::Polygons, Some(m)) => {
+                Some(m.polygons().into_iter().map(MaskRecord::Polygons).collect())
+            }
+            _ => None,
+        };
+        let detections = self
+            .bboxes()
+            .map(|bboxes| {
+                bboxes
+                    .iter()
+                    .enumerate()
+                    .map(|(i, bbox)| DetectionRecord {
+                        name: class_name(names, bbox.id()),
+                        bbox: bbox.clone(),
+                        keypoints: self.keypoints().and_then(|kpts| kpts.get(i).cloned()),
+                        mask: masks.as_ref().and_then(|masks| masks.get(i).cloned()),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        ResultRecord {
+            version: RECORD_VERSION,
+            model_id: self.model_id().map(String::from),
+            probs: self.probs().cloned(),
+            classes: self
+                .probs()
+                .map(|probs| {
+                    probs
+                        .topk(TOP_CLASSES)
+                        .into_iter()
+                        .map(|(id, confidence)| ClassRecord {
+                            id,
+                            name: class_name(names, id),
+                            confidence,
+                        })
+                        .collect()
+                })
+                .unwrap_or_default(),
+            detections,
+            obbs: self
+                .obbs()
+                .map(|obbs| {
+                    obbs.iter()
+                        .map(|obb| ObbRecord {
+                            name: class_name(names, obb.id()),
+                            obb: obb.clone(),
+                        })
+                        .collect()
+                })
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl ResultRecord {
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string(self)?)
+    }
+
+    pub fn from_json(s: &str) -> Result<Self> {
+        Self::checked(serde_json::from_str(s)?)
+    }
+
+    pub fn to_msgpack(&self) -> Result<Vec<u8>> {
+        // with field names, so records stay readable when fields are added
+        Ok(rmp_serde::to_vec_named(self)?)
+    }
+
+    pub fn from_msgpack(bytes: &[u8]) -> Result<Self> {
+        Self::checked(rmp_serde::from_slice(bytes)?)
+    }
+
+    fn checked(record: Self) -> Result<Self> {
+        if record.version > RECORD_VERSION {
+            bail!(
+                "Result record version {} is newer than the supported {RECORD_VERSION}",
+                record.version
+            );
+        }
+        Ok(record)
+    }
+
+    // Back to a `YOLOResult`. Masks come back from RLE only, polygons are not rasterized.
+    pub fn to_result(&self) -> YOLOResult {
+        let bboxes: Vec<Bbox> = self.detections.iter().map(|d| d.bbox.clone()).collect();
+        let keypoints = if self.detections.iter().any(|d| d.keypoints.is_some()) {
+            Some(
+                self.detections
+                    .iter()
+                    .map(|d| d.keypoints.clone().unwrap_or_default())
+                    .collect(),
+            )
+        } else {
+            None
+        };
+        let rles: Option<Vec<Rle>> = self
+            .detections
+            .iter()
+            .map(|d| match &d.mask {
+                Some(MaskRecord::Rle(rle)) => Some(rle.clone()),
+                _ => None,
+            })
+            .collect();
+        let masks = match rles {
+            Some(rles) if !rles.is_empty() => Some(Masks::from_rle(bboxes.clone(), &rles)),
+            _ => None,
+        };
+        YOLOResult {
+            probs: self.probs.clone(),
+            bboxes: if !bboxes.is_empty() {
+                Some(bboxes)
+            } else {
+                None
+            },
+            keypoints,
+            masks,
+            obbs: if !self.obbs.is_empty() {
+                Some(self.obbs.iter().map(|o| o.obb.clone()).collect())
+            } else {
+                None
+            },
+            model_id: self.model_id.as_deref().map(Into::into),
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use sha2::{Digest, Sha256};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use crate::{LoadError, YOLOTask};
+
+// manifest of the registered models, in the models directory
+pub const MANIFEST: &str = "manifest.toml";
+
+#[derive(Debug, Clone, PartialEq, Default, serde::Serialize, serde::Deserialize)]
+pub struct Manifest {
+    #[serde(default)]
+    models: Vec<ModelRecord>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ModelRecord {
+    // a registered model, `file` and `args` relative to the models directory
+    id: String,
+    file: String,
+    sha256: String,
+    task: Option<YOLOTask>,
+    #[serde(default)]
+    names: Vec<String>,
+    width: Option<u32>,
+    height: Option<u32>,
+    trained: Option<String>,
+    notes: Option<String>,
+    args: Option<String>,
+}
+
+impl Manifest {
+    pub fn load(dir: &Path) -> Result<Self, LoadError> {
+        let path = dir.join(MANIFEST);
+        let file = path.display().to_string();
+        let content =
+            std::fs::read_to_string(&path).map_err(|e| LoadError::Read(file.clone(), e))?;
+        let manifest: Self = toml::from_str(&content)
+            .map_err(|e| LoadError::Manifest(file.clone(), e.to_string()))?;
+
+        // ids are what results and audits refer to, so they have to be unique
+        let mut ids = BTreeSet::new();
+        for record in manifest.models.iter() {
+            if !ids.insert(record.id.as_str()) {
+                return Err(LoadError::Manifest(
+                    file,
+                    format!("model id `{}` is registered twice", record.id),
+                ));
+            }
+        }
+        Ok(manifest)
+    }
+
+    pub fn models(&self) -> &Vec<ModelRecord> {
+        &self.models
+    }
+
+    pub fn get(&self, id: &str) -> Result<&ModelRecord, LoadError> {
+        self.models
+            .iter()
+            .find(|record| record.id == id)
+            .ok_or_else(|| LoadError::Unregistered(id.to_string()))
+    }
+}
+
+impl ModelRecord {
+    pub fn id(&self) -> &String {
+        &self.id
+    }
+
+    pub fn file(&self) -> &String {
+        &self.file
+    }
+
+    pub fn sha256(&self) -> &String {
+        &self.sha256
+    }
+
+    pub fn task(&self) -> Option<&YOLOTask> {
+        self.task.as_ref()
+    }
+
+    pub fn names(&self) -> &Vec<String> {
+        &self.names
+    }
+
+    pub fn width(&self) -> Option<u32> {
+        self.width
+    }
+
+    pub fn height(&self) -> Option<u32> {
+        self.height
+    }
+
+    pub fn trained(&self) -> Option<&String> {
+        self.trained.as_ref()
+    }
+
+    pub fn notes(&self) -> Option<&String> {
+        self.notes.as_ref()
+    }
+
+    pub fn path(&self, dir: &Path) -> PathBuf {
+        dir.join(&self.file)
+    }
+
+    pub fn args_path(&self, dir: &Path) -> Option<PathBuf> {
+        self.args.as_ref().map(|args| dir.join(args))
+    }
+
+    // Reads the model file, refusing it if it is not the one that was registered.
+    pub fn read(&self, dir: &Path) -> Result<Vec<u8>, LoadError> {
+        let path = self.path(dir);
+        let file = path.display().to_string();
+        let bytes = match std::fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(LoadError::NotFound(file))
+            }
+            Err(e) => return Err(LoadError::Read(file, e)),
+        };
+        self.verify(&bytes, &file)?;
+        Ok(bytes)
+    }
+
+    pub fn verify(&self, bytes: &[u8], file: &str) -> Result<(), LoadError> {
+        let actual = sha256(bytes);
+        if !actual.eq_ignore_ascii_case(self.sha256.trim()) {
+            return Err(LoadError::Integrity {
+                file: file.to_string(),
+                expected: self.sha256.clone(),
+                actual,
+            });
+        }
+        Ok(())
+    }
+}
+
+pub fn sha256(bytes: &[u8]) -> String {
+    // lowercase hex, as printed by `sha256sum`
+    format!("{:x}", Sha256::digest(bytes))
+}
@@ -0,0 +1,361 @@
+use anyhow::Result;
+use image::DynamicImage;
+use log::{info, warn};
+use ndarray::{Array, IxDyn};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::{ModelFamily, YOLOResult, YOLOTask, YOLOv8};
+
+// expected detections of the self-test images, next to them
+const EXPECTED: &str = "expected.toml";
+const IMAGE_EXTENSIONS: [&str; 4] = ["jpg", "jpeg", "png", "bmp"];
+
+#[derive(Debug, Clone, PartialEq, Default, serde::Serialize)]
+pub struct SelfTestReport {
+    // outcome of the warm-up and self-test a model goes through when it is loaded
+    model: String,
+    warmup_ms: Vec<f32>,
+    checks: Vec<Check>,
+    images: Vec<ImageCheck>,
+    passed: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, serde::Serialize)]
+pub struct Check {
+    name: String,
+    passed: bool,
+    detail: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, serde::Serialize)]
+pub struct ImageCheck {
+    // detections of one image by class name, and the minimum counts it should have.
+    // `passed` is None when nothing is expected or the model does not know the classes,
+    // and false with an `error` when the image could not be read or run.
+    image: String,
+    detected: BTreeMap<String, usize>,
+    expected: Option<BTreeMap<String, usize>>,
+    passed: Option<bool>,
+    error: Option<String>,
+}
+
+impl SelfTestReport {
+    pub fn model(&self) -> &String {
+        &self.model
+    }
+
+    pub fn warmup_ms(&self) -> &Vec<f32> {
+        &self.warmup_ms
+    }
+
+    pub fn checks(&self) -> &Vec<Check> {
+        &self.checks
+    }
+
+    pub fn images(&self) -> &Vec<ImageCheck> {
+        &self.images
+    }
+
+    pub fn passed(&self) -> bool {
+        self.passed
+    }
+}
+
+impl Check {
+    fn new(name: &str, passed: bool, detail: String) -> Self {
+        Self {
+            name: name.to_string(),
+            passed,
+            detail,
+        }
+    }
+
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn passed(&self) -> bool {
+        self.passed
+    }
+
+    pub fn detail(&self) -> &String {
+        &self.detail
+    }
+}
+
+impl ImageCheck {
+    pub fn image(&self) -> &String {
+        &self.image
+    }
+
+    pub fn detected(&self) -> &BTreeMap<String, usize> {
+        &self.detected
+    }
+
+    pub fn expected(&self) -> Option<&BTreeMap<String, usize>> {
+        self.expected.as_ref()
+    }
+
+    pub fn passed(&self) -> Option<bool> {
+        self.passed
+    }
+
+    pub fn error(&self) -> Option<&String> {
+        self.error.as_ref()
+    }
+
+    fn fail(&mut self, error: String) {
+        self.passed = Some(false);
+        self.error = Some(error);
+    }
+}
+
+/*
+Runs `warmup` synthetic batches of the size the model will see while
+streaming, so the first real batch does not pay for the lazy initialization
+of the execution provider, and checks the output shapes of the first one
+against the task, nc, nk, nm and na. Then runs the images of `images` (if
+any) through the model and compares the detections with `expected.toml`.
+Nothing here fails the load, the report says what went wrong.
+*/
+pub fn self_test(model: &YOLOv8, name: &str, warmup: u32, images: Option<&Path>) -> SelfTestReport {
+    let mut report = SelfTestReport {
+        model: name.to_string(),
+        ..Default::default()
+    };
+
+    // synthetic batches, gray frames of the input size
+    let xs = vec![DynamicImage::new_rgb8(model.width(), model.height()); model.batch() as usize];
+    for i in 0..warmup.max(1) {
+        let start = Instant::now();
+        let ys = infer_raw(model, &xs);
+        report.warmup_ms.push(start.elapsed().as_secs_f32() * 1000.);
+        match ys {
+            Ok(ys) if i == 0 => report.checks = check_outputs(model, &ys, xs.len()),
+            Ok(_) => {}
+            Err(e) => {
+                report
+                    .checks
+                    .push(Check::new("run", false, format!("Inference failed: {e}")));
+                break;
+            }
+        }
+    }
+
+    if let Some(dir) = images {
+        match check_images(model, dir) {
+            Ok(images) => report.images = images,
+            Err(e) => report.checks.push(Check::new(
+                "images",
+                false,
+                format!("Failed to run {}: {e}", dir.display()),
+            )),
+        }
+    }
+
+    report.passed = report.checks.iter().all(|c| c.passed)
+        && report.images.iter().all(|i| i.passed != Some(false));
+    if report.passed {
+        info!(
+            "Self-test of {name} passed, warm-up: {:?} ms.",
+            report.warmup_ms
+        );
+    } else {
+        warn!("Self-test of {name} failed: {report:?}");
+    }
+    report
+}
+
+fn augs(model: &YOLOv8) -> Vec<(f32, bool)> {
+    if model.tta().is_empty() {
+        vec![(1., false)]
+    } else {
+        model.tta().clone()
+    }
+}
+
+fn infer_raw(model: &YOLOv8, xs: &[DynamicImage]) -> Result<Vec<Array<f32, IxDyn>>> {
+    // preprocess and run only, the same batch `YOLOv8::run` would build
+    let xs = model.preprocess_augmented(xs, &augs(model))?;
+    model.engine().run(xs.view(), false)
+}
+
+fn check_outputs(model: &YOLOv8, ys: &[Array<f32, IxDyn>], n: usize) -> Vec<Check> {
+    let batch = n * augs(model).len();
+    let (nc, nk, nm) = (
+        model.nc() as usize,
+        model.nk() as usize,
+        model.nm() as usize,
+    );
+
+    // expected shapes by output index, None where any size is fine
+    let family = model.engine().family();
+    let first: Vec<Option<usize>> = match model.task() {
+        YOLOTask::Classify => vec![Some(batch), Some(nc)],
+        YOLOTask::Detect if family == ModelFamily::Yolov10 => vec![Some(batch), None, Some(6)],
+        YOLOTask::Detect if family.objectness() => vec![Some(batch), None, Some(5 + nc)],
+        YOLOTask::Segment if family.objectness() => vec![Some(batch), None, Some(5 + nc + nm)],
+        YOLOTask::Detect => vec![Some(batch), Some(4 + nc), None],
+        YOLOTask::Pose => vec![Some(batch), Some(4 + nc + 3 * nk), None],
+        YOLOTask::Segment => vec![Some(batch), Some(4 + nc + nm), None],
+        YOLOTask::Obb => vec![Some(batch), Some(4 + nc + 1), None],
+    };
+    let mut expected = vec![(0, first)];
+    if let YOLOTask::Segment = model.task() {
+        expected.push((1, vec![Some(batch), Some(nm), None, None]));
+    }
+    if let Some(end_to_end) = model.engine().end_to_end() {
+        expected = vec![
+            (end_to_end.boxes, vec![Some(batch), None, Some(4)]),
+            (end_to_end.scores, vec![Some(batch), None]),
+            (end_to_end.classes, vec![Some(batch), None]),
+        ];
+    }
+
+    let n_outputs = expected.iter().map(|(i, _)| i + 1).max().unwrap_or(0);
+    let mut checks = vec![Check::new(
+        "outputs",
+        ys.len() >= n_outputs,
+        format!("{} outputs, expected {n_outputs}", ys.len()),
+    )];
+    for (i, expected) in expected.iter() {
+        let Some(y) = ys.get(*i) else {
+            continue;
+        };
+        let shape = y.shape();
+        let passed = shape.len() == expected.len()
+            && shape
+                .iter()
+                .zip(expected.iter())
+                .all(|(&s, &e)| e.is_none() || e == Some(s));
+        checks.push(Check::new(
+            &format!("output {i} shape"),
+            passed,
+            format!("{shape:?}, expected {expected:?} (nc: {nc}, nk: {nk}, nm: {nm})"),
+        ));
+    }
+
+    // the anchors have to agree with the model's own output shape
+    if let (Some(na), Some(y)) = (model.engine().na(), ys.first()) {
+        let axis = model.engine().anchor_axis();
+        let passed = y.shape().get(axis) == Some(&(na as usize));
+        checks.push(Check::new(
+            "anchors",
+            passed,
+            format!("{:?} anchors, expected {na}", y.shape().get(axis)),
+        ));
+    }
+
+    let finite = ys.iter().all(|y| y.iter().all(|v| v.is_finite()));
+    checks.push(Check::new(
+        "finite",
+        finite,
+        if finite {
+            "all outputs are finite".to_string()
+        } else {
+            "NaN or infinite values in the outputs".to_string()
+        },
+    ));
+    checks
+}
+
+fn check_images(model: &YOLOv8, dir: &Path) -> Result<Vec<ImageCheck>> {
+    // image file -> class name -> minimum number of detections
+    let expected: BTreeMap<String, BTreeMap<String, usize>> =
+        match std::fs::read_to_string(dir.join(EXPECTED)) {
+            Ok(content) => toml::from_str(&content)?,
+            Err(_) => BTreeMap::new(),
+        };
+
+    let mut files = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_image = path.extension().is_some_and(|ext| {
+            IMAGE_EXTENSIONS.contains(&ext.to_string_lossy().to_lowercase().as_str())
+        });
+        if is_image {
+            files.push(path);
+        }
+    }
+    files.sort();
+
+    // an image that cannot be read fails on its own, the others still run
+    let mut checks = vec![];
+    let mut imgs = vec![];
+    for path in files.iter() {
+        let image = path.file_name().unwrap().to_string_lossy().to_string();
+        let mut check = ImageCheck {
+            expected: expected.get(&image).cloned(),
+            image,
+            ..Default::default()
+        };
+        match image::open(path) {
+            Ok(img) => imgs.push((checks.len(), img)),
+            Err(e) => check.fail(format!("Failed to read: {e}")),
+        }
+        checks.push(check);
+    }
+
+    // batches of the model's batch size, the last one filled up with copies
+    let batch = model.batch() as usize;
+    for chunk in imgs.chunks(batch) {
+        let mut xs: Vec<_> = chunk.iter().map(|(_, img)| img.clone()).collect();
+        xs.resize(batch, xs[xs.len() - 1].clone());
+        let ys = infer_raw(model, &xs).and_then(|raw| {
+            let raw: Vec<_> = raw.into_iter().map(Arc::new).collect();
+            model.postprocess(&raw, &xs)
+        });
+        match ys {
+            Ok(ys) => {
+                for ((i, _), y) in chunk.iter().zip(ys.iter()) {
+                    let check = &mut checks[*i];
+                    check.detected = detections(model, y);
+                    check.passed =
+                        check.expected.as_ref().and_then(|expected| {
+                            // only meaningful for models that know every expected class
+                            if !expected.keys().all(|name| model.names().contains(name)) {
+                                return None;
+                            }
+                            Some(expected.iter().all(|(name, &n)| {
+                                check.detected.get(name).copied().unwrap_or(0) >= n
+                            }))
+                        });
+                }
+            }
+            Err(e) => {
+                for (i, _) in chunk.iter() {
+                    checks[*i].fail(format!("Inference failed: {e}"));
+                }
+            }
+        }
+    }
+    Ok(checks)
+}
+
+fn detections(model: &YOLOv8, y: &YOLOResult) -> BTreeMap<String, usize> {
+    // number of detections per class name; the top-1 class for classification
+    let mut ids = vec![];
+    if let Some(bboxes) = y.bboxes() {
+        ids.extend(bboxes.iter().map(|b| b.id()));
+    }
+    if let Some(obbs) = y.obbs() {
+        ids.extend(obbs.iter().map(|o| o.id()));
+    }
+    if let Some(probs) = y.probs() {
+        ids.push(probs.top1().0);
+    }
+
+    let mut counts = BTreeMap::new();
+    for id in ids {
+        let name = model
+            .names()
+            .get(id)
+            .cloned()
+            .unwrap_or_else(|| id.to_string());
+        *counts.entry(name).or_insert(0) += 1;
+    }
+    counts
+}
@@ -1,4 +1,7 @@
 use ndarray::{Array, Axis, IxDyn};
+use std::sync::Arc;
+
+use crate::{Masks, Rle};
 
 #[derive(Clone, PartialEq, Default)]
 pub struct YOLOResult {
@@ -6,7 +9,10 @@ pub struct YOLOResult {
     pub probs: Option<Embedding>,
     pub bboxes: Option<Vec<Bbox>>,
     pub keypoints: Option<Vec<Vec<Point2>>>,
-    pub masks: Option<Vec<Vec<u8>>>,
+    pub masks: Option<Masks>,
+    pub obbs: Option<Vec<Obb>>,
+    // the model that produced the results
+    pub model_id: Option<Arc<str>>,
 }
 
 impl std::fmt::Debug for YOLOResult {
@@ -22,6 +28,8 @@ impl std::fmt::Debug for YOLOResult {
                 "Masks",
                 &format_args!("{:?}", self.masks().map(|masks| masks.len())),
             )
+            .field("Obbs", &self.obbs)
+            .field("Model", &self.model_id)
             .finish()
     }
 }
@@ -31,13 +39,15 @@ impl YOLOResult {
         probs: Option<Embedding>,
         bboxes: Option<Vec<Bbox>>,
         keypoints: Option<Vec<Vec<Point2>>>,
-        masks: Option<Vec<Vec<u8>>>,
+        masks: Option<Masks>,
     ) -> Self {
         Self {
             probs,
             bboxes,
             keypoints,
             masks,
+            obbs: None,
+            model_id: None,
         }
     }
 
@@ -45,14 +55,36 @@ impl YOLOResult {
         self.probs.as_ref()
     }
 
+    pub fn classes(&self, names: &[String], k: usize) -> Option<Vec<(String, f32)>> {
+        // top-k (class name, probability) pairs of a classification result
+        self.probs().map(|probs| {
+            probs
+                .topk(k)
+                .into_iter()
+                .map(|(id, probability)| {
+                    let name = names.get(id).cloned().unwrap_or_else(|| id.to_string());
+                    (name, probability)
+                })
+                .collect()
+        })
+    }
+
     pub fn keypoints(&self) -> Option<&Vec<Vec<Point2>>> {
         self.keypoints.as_ref()
     }
 
-    pub fn masks(&self) -> Option<&Vec<Vec<u8>>> {
+    pub fn masks(&self) -> Option<&Masks> {
         self.masks.as_ref()
     }
 
+    pub fn masks_rle(&self) -> Option<Vec<Rle>> {
+        self.masks().map(|masks| masks.rle())
+    }
+
+    pub fn masks_polygons(&self) -> Option<Vec<Vec<Vec<Point2>>>> {
+        self.masks().map(|masks| masks.polygons())
+    }
+
     pub fn bboxes(&self) -> Option<&Vec<Bbox>> {
         self.bboxes.as_ref()
     }
@@ -60,9 +92,17 @@ impl YOLOResult {
     pub fn bboxes_mut(&mut self) -> Option<&mut Vec<Bbox>> {
         self.bboxes.as_mut()
     }
+
+    pub fn obbs(&self) -> Option<&Vec<Obb>> {
+        self.obbs.as_ref()
+    }
+
+    pub fn model_id(&self) -> Option<&str> {
+        self.model_id.as_deref()
+    }
 }
 
-#[derive(Debug, PartialEq, Clone, Default)]
+#[derive(Debug, PartialEq, Clone, Default, serde::Serialize, serde::Deserialize)]
 pub struct Point2 {
     // A point2d with x, y, conf
     x: f32,
@@ -96,12 +136,39 @@ impl Point2 {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Default)]
+#[derive(Debug, Clone, PartialEq, Default, serde::Serialize, serde::Deserialize)]
+#[serde(into = "EmbeddingRepr", try_from = "EmbeddingRepr")]
 pub struct Embedding {
     // An float32 n-dims tensor
     data: Array<f32, IxDyn>,
 }
 
+#[derive(serde::Serialize, serde::Deserialize)]
+struct EmbeddingRepr {
+    // row-major values and the shape they go back into
+    shape: Vec<usize>,
+    data: Vec<f32>,
+}
+
+impl From<Embedding> for EmbeddingRepr {
+    fn from(x: Embedding) -> Self {
+        Self {
+            shape: x.data.shape().to_vec(),
+            data: x.data.iter().copied().collect(),
+        }
+    }
+}
+
+impl TryFrom<EmbeddingRepr> for Embedding {
+    type Error = String;
+
+    fn try_from(x: EmbeddingRepr) -> Result<Self, Self::Error> {
+        Array::from_shape_vec(IxDyn(&x.shape), x.data)
+            .map(Self::new)
+            .map_err(|e| format!("embedding data does not fit its shape: {e}"))
+    }
+}
+
 impl Embedding {
     pub fn new(data: Array<f32, IxDyn>) -> Self {
         Self { data }
@@ -126,6 +193,27 @@ impl Embedding {
         topk
     }
 
+    pub fn is_normalized(&self) -> bool {
+        // already a probability distribution, e.g. softmax is part of the exported graph
+        self.data.iter().all(|x| (0.0..=1.0).contains(x)) && (self.data.sum() - 1.).abs() < 1e-3
+    }
+
+    pub fn softmax(&self) -> Self {
+        let max = self.data.fold(f32::MIN, |max, &x| max.max(x));
+        let exp = self.data.mapv(|x| (x - max).exp());
+        let sum = exp.sum();
+        Self::new(exp / sum)
+    }
+
+    pub fn probabilities(self) -> Self {
+        // softmax unless the model already outputs probabilities
+        if self.is_normalized() {
+            self
+        } else {
+            self.softmax()
+        }
+    }
+
     pub fn norm(&self) -> Array<f32, IxDyn> {
         let std_ = self.data.mapv(|x| x * x).sum_axis(Axis(0)).mapv(f32::sqrt);
         self.data.clone() / std_
@@ -136,7 +224,8 @@ impl Embedding {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Default)]
+#[derive(Debug, Clone, PartialEq, Default, serde::Serialize, serde::Deserialize)]
+#[serde(into = "BboxRepr", try_from = "BboxRepr")]
 pub struct Bbox {
     // a bounding box around an object
     xmin: f32,
@@ -147,6 +236,39 @@ pub struct Bbox {
     confidence: f32,
 }
 
+#[derive(serde::Serialize, serde::Deserialize)]
+struct BboxRepr {
+    // both corner and size forms are written, either one is enough to read a bbox back
+    xyxy: Option<[f32; 4]>,
+    xywh: Option<[f32; 4]>,
+    id: usize,
+    confidence: f32,
+}
+
+impl From<Bbox> for BboxRepr {
+    fn from(x: Bbox) -> Self {
+        Self {
+            xyxy: Some([x.xmin(), x.ymin(), x.xmax(), x.ymax()]),
+            xywh: Some([x.xmin, x.ymin, x.width, x.height]),
+            id: x.id,
+            confidence: x.confidence,
+        }
+    }
+}
+
+impl TryFrom<BboxRepr> for Bbox {
+    type Error = String;
+
+    fn try_from(x: BboxRepr) -> Result<Self, Self::Error> {
+        let [xmin, ymin, width, height] = match (x.xywh, x.xyxy) {
+            (Some(xywh), _) => xywh,
+            (None, Some([x1, y1, x2, y2])) => [x1, y1, x2 - x1, y2 - y1],
+            (None, None) => return Err("bbox has neither `xywh` nor `xyxy`".to_string()),
+        };
+        Ok(Self::new(xmin, ymin, width, height, x.id, x.confidence))
+    }
+}
+
 impl Bbox {
     pub fn new_from_xywh(xmin: f32, ymin: f32, width: f32, height: f32) -> Self {
         Self {
@@ -233,3 +355,210 @@ impl Bbox {
         self.intersection_area(another) / self.union(another)
     }
 }
+
+#[derive(Debug, Clone, PartialEq, Default, serde::Serialize, serde::Deserialize)]
+#[serde(into = "ObbRepr", try_from = "ObbRepr")]
+pub struct Obb {
+    // an oriented bounding box, rotated by `angle` radians around its center
+    cx: f32,
+    cy: f32,
+    width: f32,
+    height: f32,
+    angle: f32,
+    id: usize,
+    confidence: f32,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ObbRepr {
+    // the fields as they are, read back through `Obb::new` to regularize them again
+    cx: f32,
+    cy: f32,
+    width: f32,
+    height: f32,
+    angle: f32,
+    id: usize,
+    confidence: f32,
+}
+
+impl From<Obb> for ObbRepr {
+    fn from(x: Obb) -> Self {
+        Self {
+            cx: x.cx,
+            cy: x.cy,
+            width: x.width,
+            height: x.height,
+            angle: x.angle,
+            id: x.id,
+            confidence: x.confidence,
+        }
+    }
+}
+
+impl TryFrom<ObbRepr> for Obb {
+    type Error = String;
+
+    fn try_from(x: ObbRepr) -> Result<Self, Self::Error> {
+        let values = [x.cx, x.cy, x.width, x.height, x.angle, x.confidence];
+        if !values.iter().all(|v| v.is_finite()) {
+            return Err("obb has a NaN or infinite value".to_string());
+        }
+        if x.width < 0. || x.height < 0. {
+            return Err(format!("obb has a negative size {}x{}", x.width, x.height));
+        }
+        Ok(Self::new(
+            x.cx,
+            x.cy,
+            x.width,
+            x.height,
+            x.angle,
+            x.id,
+            x.confidence,
+        ))
+    }
+}
+
+impl Obb {
+    pub fn new(
+        cx: f32,
+        cy: f32,
+        width: f32,
+        height: f32,
+        angle: f32,
+        id: usize,
+        confidence: f32,
+    ) -> Self {
+        // regularize so that width is the long side and angle lies in [0, pi)
+        let (width, height, angle) = if width >= height {
+            (width, height, angle)
+        } else {
+            (height, width, angle + std::f32::consts::FRAC_PI_2)
+        };
+        Self {
+            cx,
+            cy,
+            width,
+            height,
+            angle: angle.rem_euclid(std::f32::consts::PI),
+            id,
+            confidence,
+        }
+    }
+
+    pub fn cx(&self) -> f32 {
+        self.cx
+    }
+
+    pub fn cy(&self) -> f32 {
+        self.cy
+    }
+
+    pub fn width(&self) -> f32 {
+        self.width
+    }
+
+    pub fn height(&self) -> f32 {
+        self.height
+    }
+
+    pub fn angle(&self) -> f32 {
+        self.angle
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn confidence(&self) -> f32 {
+        self.confidence
+    }
+
+    pub fn area(&self) -> f32 {
+        self.width * self.height
+    }
+
+    pub fn vertices(&self) -> [Point2; 4] {
+        // corners in order, starting from the one at (-w/2, -h/2) before rotation
+        let (sin, cos) = self.angle.sin_cos();
+        let (dx_w, dy_w) = (cos * self.width / 2., sin * self.width / 2.);
+        let (dx_h, dy_h) = (-sin * self.height / 2., cos * self.height / 2.);
+        [
+            Point2::new(self.cx - dx_w - dx_h, self.cy - dy_w - dy_h),
+            Point2::new(self.cx + dx_w - dx_h, self.cy + dy_w - dy_h),
+            Point2::new(self.cx + dx_w + dx_h, self.cy + dy_w + dy_h),
+            Point2::new(self.cx - dx_w + dx_h, self.cy - dy_w + dy_h),
+        ]
+    }
+
+    pub fn bbox(&self) -> Bbox {
+        // axis-aligned box enclosing the rotated one
+        let vertices = self.vertices();
+        let xmin = vertices.iter().map(|p| p.x()).fold(f32::MAX, f32::min);
+        let ymin = vertices.iter().map(|p| p.y()).fold(f32::MAX, f32::min);
+        let xmax = vertices.iter().map(|p| p.x()).fold(f32::MIN, f32::max);
+        let ymax = vertices.iter().map(|p| p.y()).fold(f32::MIN, f32::max);
+        Bbox::new(
+            xmin,
+            ymin,
+            xmax - xmin,
+            ymax - ymin,
+            self.id,
+            self.confidence,
+        )
+    }
+
+    pub fn intersection_area(&self, another: &Obb) -> f32 {
+        // Sutherland-Hodgman: clip self by every edge of another (both are convex)
+        let clip = another.vertices();
+        let mut polygon = self.vertices().to_vec();
+        for i in 0..clip.len() {
+            if polygon.is_empty() {
+                break;
+            }
+            let (a, b) = (&clip[i], &clip[(i + 1) % clip.len()]);
+            let inside = |p: &Point2| {
+                (b.x() - a.x()) * (p.y() - a.y()) - (b.y() - a.y()) * (p.x() - a.x()) >= 0.
+            };
+            let intersect = |p: &Point2, q: &Point2| {
+                let (dx_pq, dy_pq) = (q.x() - p.x(), q.y() - p.y());
+                let (dx_ab, dy_ab) = (b.x() - a.x(), b.y() - a.y());
+                let denom = dx_pq * dy_ab - dy_pq * dx_ab;
+                let t = ((a.x() - p.x()) * dy_ab - (a.y() - p.y()) * dx_ab) / denom;
+                Point2::new(p.x() + t * dx_pq, p.y() + t * dy_pq)
+            };
+
+            let input = std::mem::take(&mut polygon);
+            for j in 0..input.len() {
+                let (p, q) = (&input[j], &input[(j + 1) % input.len()]);
+                match (inside(p), inside(q)) {
+                    (true, true) => polygon.push(q.clone()),
+                    (true, false) => polygon.push(intersect(p, q)),
+                    (false, true) => {
+                        polygon.push(intersect(p, q));
+                        polygon.push(q.clone());
+                    }
+                    (false, false) => {}
+                }
+            }
+        }
+        polygon_area(&polygon)
+    }
+
+    pub fn union(&self, another: &Obb) -> f32 {
+        self.area() + another.area() - self.intersection_area(another)
+    }
+
+    pub fn iou(&self, another: &Obb) -> f32 {
+        self.intersection_area(another) / self.union(another)
+    }
+}
+
+fn polygon_area(points: &[Point2]) -> f32 {
+    // shoelace formula
+    let mut area = 0.;
+    for i in 0..points.len() {
+        let (p, q) = (&points[i], &points[(i + 1) % points.len()]);
+        area += p.x() * q.y() - q.x() * p.y();
+    }
+    area.abs() / 2.
+}
@@ -0,0 +1,99 @@
+mod common;
+
+use app::{Bbox, ExportFormat, Exporter, MaskEncoding, Rle};
+use common::{empty_segmentation, mask};
+use image::DynamicImage;
+use std::path::PathBuf;
+
+type Pixel = fn(u32, u32) -> bool;
+
+fn export_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("coco_export_{}_{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+#[test]
+fn compressed_rle_matches_pycocotools() {
+    // strings of `rleToString` in pycocotools' maskApi.c, for the same masks
+    let cases: [(u32, u32, Pixel, &str); 4] = [
+        (1, 1, |_, _| true, "01"),
+        (4, 3, |_, _| false, "<"),
+        (
+            40,
+            30,
+            |x, y| (5..25).contains(&x) && (3..20).contains(&y),
+            "i4a0=0000000000000000000000000000000000000o=",
+        ),
+        (
+            9,
+            12,
+            |x, y| (x + 2 * y) % 7 < 2 || (x < 3 && y > 6),
+            "0130O50K140LO50K10O020N0100000O020N010O11OO011OO10O0O",
+        ),
+    ];
+    for (width, height, f, expected) in cases {
+        let rle = Rle::encode(&mask(width, height, f), width, height);
+        assert_eq!(rle.compressed(), expected, "{width}x{height} mask");
+    }
+}
+
+#[test]
+fn empty_masks_are_labelled_with_their_box() {
+    let dir = export_dir("polygons");
+    let names = vec!["bolt".to_string(), "nut".to_string()];
+    let mut exporter = Exporter::new(
+        &dir,
+        ExportFormat::CocoAnnotations,
+        names,
+        MaskEncoding::Polygons,
+    )
+    .unwrap();
+    exporter
+        .add(
+            "a.png",
+            &DynamicImage::new_rgb8(32, 32),
+            &empty_segmentation(vec![Bbox::new(4., 4., 20., 12., 1, 0.8)]),
+        )
+        .unwrap();
+    let path = exporter.finish().unwrap();
+
+    let dataset: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+    let annotation = &dataset["annotations"][0];
+    assert_eq!(
+        annotation["segmentation"],
+        serde_json::json!([[4., 4., 24., 4., 24., 16., 4., 16.]])
+    );
+    assert_eq!(annotation["area"], 240.);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn results_lists_carry_rle() {
+    let dir = export_dir("results");
+    let mut exporter = Exporter::new(
+        &dir,
+        ExportFormat::CocoResults,
+        vec!["bolt".to_string(), "nut".to_string()],
+        MaskEncoding::Polygons,
+    )
+    .unwrap();
+    exporter
+        .add(
+            "a.png",
+            &DynamicImage::new_rgb8(32, 32),
+            &empty_segmentation(vec![Bbox::new(4., 4., 20., 12., 1, 0.8)]),
+        )
+        .unwrap();
+    let path = exporter.finish().unwrap();
+
+    let results: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+    assert_eq!(
+        results[0]["segmentation"]["size"],
+        serde_json::json!([32, 32])
+    );
+    assert!(results[0]["segmentation"]["counts"].is_string());
+    std::fs::remove_dir_all(dir).unwrap();
+}
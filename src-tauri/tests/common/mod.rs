@@ -0,0 +1,47 @@
+// fixtures shared by the integration tests, not every test uses all of them
+#![allow(dead_code)]
+
+use app::{Bbox, Masks, YOLOResult};
+use ndarray::{Array, Array2, IxDyn};
+use std::sync::Arc;
+
+pub fn mask(width: u32, height: u32, f: impl Fn(u32, u32) -> bool) -> Vec<u8> {
+    // row-major with 255 as foreground, like `Rle::decode`
+    let mut mask = vec![0u8; (width * height) as usize];
+    for y in 0..height {
+        for x in 0..width {
+            if f(x, y) {
+                mask[(y * width + x) as usize] = 255;
+            }
+        }
+    }
+    mask
+}
+
+pub fn segmentation(bboxes: Vec<Bbox>) -> YOLOResult {
+    // one proto channel, positive on the left half only, of a 32x32 image
+    let mut protos = Array::zeros(IxDyn(&[1, 1, 16, 16]));
+    protos.slice_mut(ndarray::s![0, 0, .., ..8]).fill(1.);
+    protos.slice_mut(ndarray::s![0, 0, .., 8..]).fill(-1.);
+    with_protos(protos, bboxes)
+}
+
+pub fn empty_segmentation(bboxes: Vec<Bbox>) -> YOLOResult {
+    // one proto channel that is negative everywhere, so no mask has foreground
+    with_protos(Array::from_elem(IxDyn(&[1, 1, 16, 16]), -1.), bboxes)
+}
+
+fn with_protos(protos: Array<f32, IxDyn>, bboxes: Vec<Bbox>) -> YOLOResult {
+    YOLOResult {
+        masks: Some(Masks::new(
+            Arc::new(protos),
+            0,
+            Array2::ones((bboxes.len(), 1)),
+            bboxes.clone(),
+            32,
+            32,
+        )),
+        bboxes: Some(bboxes),
+        ..Default::default()
+    }
+}
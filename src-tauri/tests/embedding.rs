@@ -0,0 +1,52 @@
+use app::Embedding;
+use ndarray::{Array, IxDyn};
+
+fn embedding(xs: &[f32]) -> Embedding {
+    Embedding::new(Array::from_shape_vec(IxDyn(&[xs.len()]), xs.to_vec()).unwrap())
+}
+
+#[test]
+fn probabilities_are_kept_as_they_are() {
+    for xs in [&[0.1, 0.7, 0.2][..], &[0., 1., 0.], &[0.25; 4]] {
+        let probs = embedding(xs);
+        assert!(probs.is_normalized());
+        assert_eq!(probs.clone().probabilities(), probs);
+    }
+}
+
+#[test]
+fn logits_go_through_softmax() {
+    // negative, or in [0, 1] without summing to 1
+    for xs in [&[2.5, -1.0, 0.3][..], &[0.4, 0.4, 0.4], &[0.1, 0.2, 0.3]] {
+        let logits = embedding(xs);
+        assert!(!logits.is_normalized());
+        let probs = logits.clone().probabilities();
+        assert_eq!(probs, logits.softmax());
+        assert!(probs.is_normalized());
+    }
+}
+
+#[test]
+fn softmax_keeps_the_order_of_the_logits() {
+    let probs = embedding(&[1.0, 3.0, -2.0, 2.0]).probabilities();
+    let order: Vec<_> = probs.topk(4).iter().map(|(id, _)| *id).collect();
+    assert_eq!(order, vec![1, 3, 0, 2]);
+    assert_eq!(probs.top1().0, 1);
+
+    let exp = [1f32.exp(), 3f32.exp(), (-2f32).exp(), 2f32.exp()];
+    let sum: f32 = exp.iter().sum();
+    for (p, e) in probs.data().iter().zip(exp) {
+        assert!((p - e / sum).abs() < 1e-6);
+    }
+
+    // large logits do not overflow
+    let probs = embedding(&[1000., 999.]).probabilities();
+    assert!((probs.data()[0] - 1. / (1. + (-1f32).exp())).abs() < 1e-6);
+}
+
+#[test]
+fn topk_of_probabilities() {
+    let probs = embedding(&[0.05, 0.6, 0.1, 0.25]);
+    assert_eq!(probs.topk(2), vec![(1, 0.6), (3, 0.25)]);
+    assert_eq!(probs.topk(10).len(), 4);
+}
@@ -0,0 +1,80 @@
+use app::EndToEnd;
+
+fn find(outputs: &[(&str, &[i32])]) -> Option<EndToEnd> {
+    let names: Vec<String> = outputs.iter().map(|(name, _)| name.to_string()).collect();
+    let shapes: Vec<Vec<i32>> = outputs.iter().map(|(_, shape)| shape.to_vec()).collect();
+    EndToEnd::find(&names, &shapes)
+}
+
+#[test]
+fn efficient_nms_outputs() {
+    let end_to_end = find(&[
+        ("num_dets", &[1, 1]),
+        ("det_boxes", &[1, 100, 4]),
+        ("det_scores", &[1, 100]),
+        ("det_classes", &[1, 100]),
+    ]);
+    assert_eq!(
+        end_to_end,
+        Some(EndToEnd {
+            num_dets: 0,
+            boxes: 1,
+            scores: 2,
+            classes: 3,
+        })
+    );
+}
+
+#[test]
+fn names_are_matched_in_any_order_and_case() {
+    let end_to_end = find(&[
+        ("Labels", &[-1, -1]),
+        ("BOXES", &[-1, -1, 4]),
+        ("num_detections", &[-1]),
+        ("Scores", &[-1, -1]),
+    ]);
+    assert_eq!(
+        end_to_end,
+        Some(EndToEnd {
+            num_dets: 2,
+            boxes: 1,
+            scores: 3,
+            classes: 0,
+        })
+    );
+}
+
+#[test]
+fn wrong_ranks_or_missing_outputs_are_not_end_to_end() {
+    // a plain detection model
+    assert_eq!(find(&[("output0", &[1, 84, 8400])]), None);
+    // scores of every class instead of the best one
+    assert_eq!(
+        find(&[
+            ("num_dets", &[1, 1]),
+            ("boxes", &[1, 100, 4]),
+            ("scores", &[1, 100, 80]),
+            ("classes", &[1, 100]),
+        ]),
+        None
+    );
+    // no count of detections
+    assert_eq!(
+        find(&[
+            ("boxes", &[1, 100, 4]),
+            ("scores", &[1, 100]),
+            ("classes", &[1, 100]),
+        ]),
+        None
+    );
+    // boxes that are not x1y1x2y2
+    assert_eq!(
+        find(&[
+            ("num_dets", &[1, 1]),
+            ("boxes", &[1, 100, 5]),
+            ("scores", &[1, 100]),
+            ("classes", &[1, 100]),
+        ]),
+        None
+    );
+}
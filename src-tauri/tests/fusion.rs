@@ -0,0 +1,28 @@
+use app::{weighted_boxes_fusion, Bbox};
+
+#[test]
+fn fused_confidence_stays_within_one() {
+    let xs = vec![
+        vec![Bbox::new(10., 10., 20., 20., 0, 0.9)],
+        vec![Bbox::new(11., 10., 20., 20., 0, 0.8)],
+    ];
+    let fused = weighted_boxes_fusion(&xs, &[3., 1.], 0.5);
+    assert_eq!(fused.len(), 1);
+    // found by both models: the mean confidence weighted by model
+    let expected = (0.9 * 3. + 0.8) / 4.;
+    assert!((fused[0].confidence() - expected).abs() < 1e-6);
+}
+
+#[test]
+fn boxes_of_one_model_count_once() {
+    let xs = vec![
+        vec![
+            Bbox::new(10., 10., 20., 20., 0, 0.8),
+            Bbox::new(11., 10., 20., 20., 0, 0.8),
+        ],
+        vec![],
+    ];
+    let fused = weighted_boxes_fusion(&xs, &[1., 1.], 0.5);
+    assert_eq!(fused.len(), 1);
+    assert!((fused[0].confidence() - 0.4).abs() < 1e-6);
+}
@@ -0,0 +1,50 @@
+mod common;
+
+use app::{Bbox, ExportFormat, Exporter, MaskEncoding};
+use common::segmentation;
+use image::DynamicImage;
+use std::path::Path;
+
+#[test]
+fn yolo_segments_are_never_mixed_with_boxes() {
+    let dir = std::env::temp_dir().join(format!("label_export_yolo_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let mut exporter = Exporter::new(
+        &dir,
+        ExportFormat::Yolo,
+        vec!["bolt".to_string(), "nut".to_string()],
+        MaskEncoding::Polygons,
+    )
+    .unwrap();
+    exporter
+        .add(
+            "a.png",
+            &DynamicImage::new_rgb8(32, 32),
+            // the second box is on the negative half of the protos and has no mask
+            &segmentation(vec![
+                Bbox::new(4., 4., 8., 8., 1, 0.8),
+                Bbox::new(20., 8., 8., 16., 0, 0.6),
+            ]),
+        )
+        .unwrap();
+    let yaml = std::fs::read_to_string(exporter.finish().unwrap()).unwrap();
+
+    let labels = std::fs::read_to_string(dir.join("labels/a.txt")).unwrap();
+    let lines: Vec<Vec<&str>> = labels.lines().map(|l| l.split(' ').collect()).collect();
+    assert_eq!(lines.len(), 2);
+    // a class and at least 3 points each, never `class cx cy w h`
+    assert!(lines.iter().all(|l| l.len() >= 7 && l.len() % 2 == 1));
+    // the box of the empty mask as a polygon
+    assert_eq!(
+        lines[1],
+        [
+            "0", "0.625000", "0.250000", "0.875000", "0.250000", "0.875000", "0.750000",
+            "0.625000", "0.750000"
+        ]
+    );
+
+    let path = yaml.lines().next().unwrap().trim_start_matches("path: ");
+    let path: String = serde_json::from_str(path).unwrap();
+    assert!(Path::new(&path).is_absolute());
+    std::fs::remove_dir_all(dir).unwrap();
+}
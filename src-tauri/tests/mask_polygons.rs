@@ -0,0 +1,37 @@
+mod common;
+
+use app::mask::polygons;
+use common::mask;
+
+fn bounds(polygon: &[app::Point2]) -> (f32, f32, f32, f32) {
+    polygon.iter().fold(
+        (f32::MAX, f32::MAX, f32::MIN, f32::MIN),
+        |(x0, y0, x1, y1), p| (x0.min(p.x()), y0.min(p.y()), x1.max(p.x()), y1.max(p.y())),
+    )
+}
+
+#[test]
+fn full_mask_is_traced() {
+    // every pixel touches the border, which contour tracing alone skips
+    let polygons = polygons(&mask(6, 4, |_, _| true), 6, 4);
+    assert_eq!(polygons.len(), 1);
+    assert_eq!(bounds(&polygons[0]), (0., 0., 5., 3.));
+}
+
+#[test]
+fn regions_on_the_border_keep_mask_coordinates() {
+    let polygons = polygons(
+        &mask(10, 8, |x, y| (x < 3 && y < 3) || (x >= 6 && y >= 5)),
+        10,
+        8,
+    );
+    let mut bounds: Vec<_> = polygons.iter().map(|p| bounds(p)).collect();
+    bounds.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    assert_eq!(bounds, vec![(0., 0., 2., 2.), (6., 5., 9., 7.)]);
+}
+
+#[test]
+fn empty_mask_has_no_polygons() {
+    assert!(polygons(&mask(5, 5, |_, _| false), 5, 5).is_empty());
+    assert!(polygons(&[], 0, 0).is_empty());
+}
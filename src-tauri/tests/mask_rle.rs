@@ -0,0 +1,34 @@
+mod common;
+
+use app::{Bbox, Masks, Rle};
+use common::mask;
+
+#[test]
+fn crop_runs_match_the_full_mask() {
+    // boxes inside the image, on each border, covering all of it, and with nothing inside
+    let (width, height) = (24, 16);
+    let boxes = [
+        Bbox::new(5., 3., 9., 7., 0, 0.9),
+        Bbox::new(0., 0., 6., 5., 0, 0.9),
+        Bbox::new(17., 10., 7., 6., 0, 0.9),
+        Bbox::new(0., 0., 24., 16., 0, 0.9),
+        Bbox::new(8., 8., 4., 4., 0, 0.9),
+    ];
+    let fills: [fn(u32, u32) -> bool; 5] = [
+        |x, y| (x + y) % 3 == 0,
+        |x, y| x < 3 || y == 4,
+        |_, _| true,
+        |x, y| (x * 7 + y * 3) % 5 < 2,
+        |_, _| false,
+    ];
+    for (bbox, fill) in boxes.iter().zip(fills) {
+        let inside = |x: u32, y: u32| {
+            let (x, y) = (x as f32, y as f32);
+            x >= bbox.xmin() && x < bbox.xmax() && y >= bbox.ymin() && y < bbox.ymax()
+        };
+        let full = mask(width, height, |x, y| inside(x, y) && fill(x, y));
+        let rle = Rle::encode(&full, width, height);
+        let masks = Masks::from_rle(vec![bbox.clone()], std::slice::from_ref(&rle));
+        assert_eq!(masks.rle(), vec![rle], "{bbox:?}");
+    }
+}
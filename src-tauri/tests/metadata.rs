@@ -0,0 +1,61 @@
+use app::metadata::{kpt_shape, load_labels, names, parse, skeleton};
+
+#[test]
+fn dict_names_keep_quotes_and_colons() {
+    // as Ultralytics writes them with Python's str()
+    let yaml = parse(
+        r#"{0: 'person', 1: "yellow_lady's_slipper", 2: 'ratio 1:2', 3: 'say "hi"', 4: 'it\'s'}"#,
+    )
+    .unwrap();
+    assert_eq!(
+        names(&yaml).unwrap(),
+        vec![
+            "person",
+            "yellow_lady's_slipper",
+            "ratio 1:2",
+            "say \"hi\"",
+            "it's"
+        ]
+    );
+}
+
+#[test]
+fn dict_names_are_ordered_by_id() {
+    let yaml = parse("{1: 'nut', 0: 'bolt', 2: 'washer'}").unwrap();
+    assert_eq!(names(&yaml).unwrap(), vec!["bolt", "nut", "washer"]);
+
+    // a gap in the ids is not a complete list of names
+    let yaml = parse("{0: 'bolt', 2: 'washer'}").unwrap();
+    assert_eq!(names(&yaml), None);
+}
+
+#[test]
+fn list_names() {
+    let yaml = parse("['person', 'bicycle', 'traffic light: red', 7]").unwrap();
+    assert_eq!(
+        names(&yaml).unwrap(),
+        vec!["person", "bicycle", "traffic light: red", "7"]
+    );
+}
+
+#[test]
+fn keypoint_shape_and_skeleton() {
+    assert_eq!(kpt_shape(&parse("[17, 3]").unwrap()), Some((17, 3)));
+    assert_eq!(kpt_shape(&parse("[21]").unwrap()), None);
+    assert_eq!(kpt_shape(&parse("'17, 3'").unwrap()), None);
+    assert_eq!(
+        skeleton(&parse("[[15, 13], [13, 11]]").unwrap()),
+        Some(vec![(15, 13), (13, 11)])
+    );
+}
+
+#[test]
+fn labels_from_data_yaml() {
+    let path = std::env::temp_dir().join(format!("metadata_{}.yaml", std::process::id()));
+    std::fs::write(&path, "nc: 2\nnames:\n  0: bolt\n  1: \"hex: nut\"\n").unwrap();
+    assert_eq!(load_labels(&path).unwrap(), vec!["bolt", "hex: nut"]);
+
+    std::fs::write(&path, "nc: 3\nnames: [bolt, nut]\n").unwrap();
+    assert!(load_labels(&path).is_err());
+    std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,60 @@
+use app::{ModelFamily, YOLOTask};
+
+fn guess(description: &str) -> ModelFamily {
+    // an output shape that would otherwise be guessed as YOLOv5
+    ModelFamily::guess(Some(description), &YOLOTask::Detect, &[1, 8400, 85])
+}
+
+#[test]
+fn ultralytics_descriptions() {
+    assert_eq!(
+        guess("Ultralytics YOLOv8n model trained on coco.yaml"),
+        ModelFamily::Yolov8
+    );
+    assert_eq!(
+        guess("Ultralytics YOLO11s-seg model trained on coco.yaml"),
+        ModelFamily::Yolo11
+    );
+    assert_eq!(
+        guess("Ultralytics YOLOv10n model trained on coco.yaml"),
+        ModelFamily::Yolov10
+    );
+    // same layout as YOLOv8, with a warning
+    assert_eq!(
+        guess("Ultralytics YOLOv5nu model trained on coco.yaml"),
+        ModelFamily::Yolov8
+    );
+    assert_eq!(
+        guess("Ultralytics YOLOv9c model trained on coco.yaml"),
+        ModelFamily::Yolov8
+    );
+}
+
+#[test]
+fn other_descriptions_fall_back_to_the_output_shape() {
+    assert_eq!(guess("yolov5s exported by export.py"), ModelFamily::Yolov5);
+    assert_eq!(
+        ModelFamily::guess(None, &YOLOTask::Detect, &[1, 84, 8400]),
+        ModelFamily::Yolov8
+    );
+}
+
+#[test]
+fn output_shapes() {
+    let detect = |shape: &[i32]| ModelFamily::guess(None, &YOLOTask::Detect, shape);
+    assert_eq!(detect(&[1, 300, 6]), ModelFamily::Yolov10);
+    assert_eq!(detect(&[1, 25200, 85]), ModelFamily::Yolov5);
+    assert_eq!(detect(&[-1, -1, 85]), ModelFamily::Yolov5);
+    assert_eq!(detect(&[1, 84, 8400]), ModelFamily::Yolov8);
+    assert_eq!(detect(&[1, 84]), ModelFamily::Yolov8);
+
+    // 300 rows of 6 values are only YOLOv10 for detection
+    assert_eq!(
+        ModelFamily::guess(None, &YOLOTask::Segment, &[1, 300, 6]),
+        ModelFamily::Yolov5
+    );
+    assert_eq!(
+        ModelFamily::guess(None, &YOLOTask::Classify, &[1, 25200, 85]),
+        ModelFamily::Yolov8
+    );
+}
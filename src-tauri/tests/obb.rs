@@ -0,0 +1,62 @@
+use app::{non_max_suppression_obb, Obb};
+use std::f32::consts::{FRAC_PI_2, FRAC_PI_4, SQRT_2};
+
+fn square(angle: f32, confidence: f32) -> Obb {
+    Obb::new(10., 10., 2., 2., angle, 0, confidence)
+}
+
+#[test]
+fn identical_boxes_overlap_fully() {
+    let obb = Obb::new(5., 3., 8., 2., 0.3, 1, 0.9);
+    assert!((obb.intersection_area(&obb) - 16.).abs() < 1e-4);
+    assert!((obb.iou(&obb) - 1.).abs() < 1e-5);
+}
+
+#[test]
+fn disjoint_boxes_do_not_overlap() {
+    let a = Obb::new(0., 0., 4., 2., 0.5, 0, 0.9);
+    let b = Obb::new(20., 20., 4., 2., 1.2, 0, 0.9);
+    assert_eq!(a.intersection_area(&b), 0.);
+    assert_eq!(a.iou(&b), 0.);
+}
+
+#[test]
+fn square_rotated_by_45_degrees() {
+    // the overlap is a regular octagon of 2 s^2 (sqrt 2 - 1), the IoU is 1 / sqrt 2
+    let (a, b) = (square(0., 0.9), square(FRAC_PI_4, 0.9));
+    assert!((a.intersection_area(&b) - 8. * (SQRT_2 - 1.)).abs() < 1e-4);
+    assert!((a.iou(&b) - 1. / SQRT_2).abs() < 1e-4);
+    assert!((b.iou(&a) - a.iou(&b)).abs() < 1e-5);
+}
+
+#[test]
+fn nms_keeps_the_more_confident_box() {
+    let mut xs = vec![
+        square(0.05, 0.6),
+        square(0., 0.9),
+        Obb::new(30., 30., 2., 2., 0., 0, 0.3),
+    ];
+    non_max_suppression_obb(&mut xs, 0.5);
+    let kept: Vec<_> = xs.iter().map(|x| x.confidence()).collect();
+    assert_eq!(kept, vec![0.9, 0.3]);
+
+    // below the threshold both stay
+    let mut xs = vec![square(0., 0.9), square(FRAC_PI_4, 0.6)];
+    non_max_suppression_obb(&mut xs, 0.75);
+    assert_eq!(xs.len(), 2);
+}
+
+#[test]
+fn deserialized_boxes_are_regularized() {
+    let obb: Obb = serde_json::from_str(
+        r#"{"cx": 1, "cy": 2, "width": 2, "height": 6, "angle": -0.5, "id": 3, "confidence": 0.5}"#,
+    )
+    .unwrap();
+    assert_eq!(obb, Obb::new(1., 2., 2., 6., -0.5, 3, 0.5));
+    assert_eq!(obb.width(), 6.);
+    assert!((obb.angle() - (FRAC_PI_2 - 0.5)).abs() < 1e-6);
+
+    let negative =
+        r#"{"cx": 1, "cy": 2, "width": -2, "height": 6, "angle": 0, "id": 3, "confidence": 0.5}"#;
+    assert!(serde_json::from_str::<Obb>(negative).is_err());
+}
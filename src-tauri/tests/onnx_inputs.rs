@@ -0,0 +1,136 @@
+use app::onnx::{inputs, OnnxInput};
+use app::LoadError;
+use ort::tensor::TensorElementDataType;
+
+// protobuf encoding of the few ModelProto fields the reader looks at
+
+fn varint(mut x: u64) -> Vec<u8> {
+    let mut out = vec![];
+    loop {
+        let b = (x & 0x7f) as u8;
+        x >>= 7;
+        if x == 0 {
+            out.push(b);
+            return out;
+        }
+        out.push(b | 0x80);
+    }
+}
+
+fn int_field(number: u64, x: u64) -> Vec<u8> {
+    [varint(number << 3), varint(x)].concat()
+}
+
+fn bytes_field(number: u64, bytes: &[u8]) -> Vec<u8> {
+    [
+        varint(number << 3 | 2),
+        varint(bytes.len() as u64),
+        bytes.to_vec(),
+    ]
+    .concat()
+}
+
+enum Dim {
+    Value(u64),
+    Param(&'static str),
+}
+
+fn value_info(name: &str, elem_type: u64, dims: &[Dim]) -> Vec<u8> {
+    let shape: Vec<u8> = dims
+        .iter()
+        .flat_map(|dim| {
+            let dim = match dim {
+                Dim::Value(x) => int_field(1, *x),
+                Dim::Param(name) => bytes_field(2, name.as_bytes()),
+            };
+            bytes_field(1, &dim)
+        })
+        .collect();
+    let tensor_type = [int_field(1, elem_type), bytes_field(2, &shape)].concat();
+    let type_proto = bytes_field(1, &tensor_type);
+    [bytes_field(1, name.as_bytes()), bytes_field(2, &type_proto)].concat()
+}
+
+fn model(graph: &[Vec<u8>]) -> Vec<u8> {
+    // ir_version, then the graph
+    [int_field(1, 8), bytes_field(7, &graph.concat())].concat()
+}
+
+fn input(number: u64, value_info: Vec<u8>) -> Vec<u8> {
+    bytes_field(number, &value_info)
+}
+
+fn images() -> Vec<u8> {
+    input(
+        11,
+        value_info(
+            "images",
+            1,
+            &[
+                Dim::Param("batch"),
+                Dim::Value(3),
+                Dim::Value(640),
+                Dim::Value(640),
+            ],
+        ),
+    )
+}
+
+#[test]
+fn fixed_and_symbolic_dims() {
+    assert_eq!(
+        inputs(&model(&[images()])).unwrap(),
+        vec![OnnxInput {
+            name: "images".to_string(),
+            dtype: TensorElementDataType::Float32,
+            shape: vec![-1, 3, 640, 640],
+        }]
+    );
+}
+
+#[test]
+fn initializers_listed_as_inputs_are_skipped() {
+    let weight = bytes_field(5, &bytes_field(8, b"conv.weight"));
+    let weight_input = input(
+        11,
+        value_info(
+            "conv.weight",
+            1,
+            &[Dim::Value(16), Dim::Value(3), Dim::Value(3), Dim::Value(3)],
+        ),
+    );
+    let half = input(11, value_info("half", 10, &[Dim::Value(1), Dim::Value(3)]));
+    let found = inputs(&model(&[weight, weight_input, images(), half])).unwrap();
+    let names: Vec<_> = found.iter().map(|i| i.name.as_str()).collect();
+    assert_eq!(names, vec!["images", "half"]);
+    assert_eq!(found[1].dtype, TensorElementDataType::Float16);
+}
+
+#[test]
+fn unknown_elem_type_is_an_error() {
+    let strings = input(11, value_info("text", 8, &[Dim::Value(1)]));
+    assert!(matches!(
+        inputs(&model(&[strings])),
+        Err(LoadError::Dtype(_))
+    ));
+}
+
+#[test]
+fn truncated_and_garbage_buffers_are_errors() {
+    let bytes = model(&[images()]);
+    for n in [1, 5, bytes.len() / 2, bytes.len() - 1] {
+        assert!(
+            matches!(inputs(&bytes[..n]), Err(LoadError::Onnx(_))),
+            "truncated to {n} bytes"
+        );
+    }
+    let garbage: Vec<u8> = (0..256u32).map(|i| (i * 167 + 13) as u8).collect();
+    for buf in [&[][..], &[0xff; 16][..], &garbage[..], b"not an onnx model"] {
+        assert!(matches!(inputs(buf), Err(LoadError::Onnx(_))));
+    }
+}
+
+#[test]
+fn graph_without_inputs_is_an_error() {
+    assert!(matches!(inputs(&model(&[])), Err(LoadError::Onnx(_))));
+}
@@ -0,0 +1,106 @@
+use app::registry::sha256;
+use app::{Args, LoadError, Manifest, YOLOv8};
+use std::path::{Path, PathBuf};
+
+const MODEL: &[u8] = b"registered model bytes";
+
+fn models_dir(name: &str, manifest: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("registry_{}_{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("manifest.toml"), manifest).unwrap();
+    dir
+}
+
+fn record(id: &str, file: &str) -> String {
+    format!(
+        "[[models]]\nid = \"{id}\"\nfile = \"{file}\"\nsha256 = \"{}\"\ntask = \"Detect\"\n\n",
+        sha256(MODEL)
+    )
+}
+
+#[test]
+fn sha256_is_lowercase_hex() {
+    assert_eq!(
+        sha256(b"abc"),
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+}
+
+#[test]
+fn registered_model_is_verified() {
+    let dir = models_dir("verified", &record("fod-v1", "fod.onnx"));
+    std::fs::write(dir.join("fod.onnx"), MODEL).unwrap();
+    let manifest = Manifest::load(&dir).unwrap();
+    let record = manifest.get("fod-v1").unwrap();
+    assert_eq!(record.read(&dir).unwrap(), MODEL);
+    assert!(matches!(
+        manifest.get("fod-v2"),
+        Err(LoadError::Unregistered(id)) if id == "fod-v2"
+    ));
+
+    // one changed byte and the file is refused
+    let mut tampered = MODEL.to_vec();
+    tampered[0] ^= 1;
+    std::fs::write(dir.join("fod.onnx"), &tampered).unwrap();
+    match record.read(&dir) {
+        Err(LoadError::Integrity {
+            expected, actual, ..
+        }) => {
+            assert_eq!(expected, sha256(MODEL));
+            assert_eq!(actual, sha256(&tampered));
+        }
+        other => panic!("expected an integrity error, got {other:?}"),
+    }
+
+    std::fs::remove_file(dir.join("fod.onnx")).unwrap();
+    assert!(matches!(record.read(&dir), Err(LoadError::NotFound(_))));
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn invalid_manifests_are_refused() {
+    let dir = models_dir(
+        "duplicate",
+        &[record("fod-v1", "a.onnx"), record("fod-v1", "b.onnx")].concat(),
+    );
+    assert!(matches!(
+        Manifest::load(&dir),
+        Err(LoadError::Manifest(_, e)) if e.contains("fod-v1")
+    ));
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert!(matches!(Manifest::load(&dir), Err(LoadError::Read(..))));
+}
+
+#[test]
+fn model_id_takes_precedence_over_the_model_path() {
+    let dir = models_dir("precedence", &record("fod-v1", "fod.onnx"));
+    std::fs::write(dir.join("fod.onnx"), b"not the registered bytes").unwrap();
+    let args = Args {
+        model_id: Some("fod-v1".to_string()),
+        models_dir: dir.to_string_lossy().to_string(),
+        model: "./no/such/legacy.onnx".to_string(),
+        ..Default::default()
+    };
+    assert!(matches!(
+        YOLOv8::new(args),
+        Err(LoadError::Integrity { .. })
+    ));
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn model_path_loads_without_model_id() {
+    // args files from before the manifest still name the model file directly
+    let legacy = Path::new("./no/such/legacy.onnx");
+    let args = Args {
+        model: legacy.to_string_lossy().to_string(),
+        ..Default::default()
+    };
+    assert!(args.model_id.is_none());
+    assert!(matches!(
+        YOLOv8::new(args),
+        Err(LoadError::NotFound(file)) if file == legacy.to_string_lossy()
+    ));
+}
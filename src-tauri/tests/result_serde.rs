@@ -0,0 +1,138 @@
+mod common;
+
+use app::{Bbox, Embedding, MaskEncoding, MaskRecord, Obb, Point2, ResultRecord, YOLOResult};
+use ndarray::Array;
+use std::sync::Arc;
+
+fn names() -> Vec<String> {
+    ["bolt", "nut", "wrench"].map(String::from).to_vec()
+}
+
+fn detections() -> YOLOResult {
+    YOLOResult {
+        bboxes: Some(vec![
+            Bbox::new(10., 20., 30., 40., 0, 0.9),
+            Bbox::new(50.5, 60.25, 5., 8., 2, 0.55),
+        ]),
+        keypoints: Some(vec![
+            vec![Point2::new_with_conf(12., 22., 0.8), Point2::new(0., 0.)],
+            vec![Point2::new_with_conf(52., 62., 0.7), Point2::new(53., 63.)],
+        ]),
+        model_id: Some(Arc::from("fod-v3")),
+        ..Default::default()
+    }
+}
+
+fn segmentation() -> YOLOResult {
+    common::segmentation(vec![
+        Bbox::new(4., 4., 20., 12., 1, 0.8),
+        Bbox::new(0., 20., 32., 10., 0, 0.6),
+    ])
+}
+
+#[test]
+fn detections_round_trip_json_and_msgpack() {
+    let y = detections();
+    let record = y.to_record(&names(), MaskEncoding::Rle);
+    assert_eq!(record.detections[0].name, "bolt");
+    assert_eq!(record.detections[1].name, "wrench");
+    assert_eq!(record.model_id.as_deref(), Some("fod-v3"));
+
+    let json = ResultRecord::from_json(&record.to_json().unwrap()).unwrap();
+    assert_eq!(json, record);
+    assert_eq!(json.to_result(), y);
+
+    let msgpack = ResultRecord::from_msgpack(&record.to_msgpack().unwrap()).unwrap();
+    assert_eq!(msgpack, record);
+    assert_eq!(msgpack.to_result(), y);
+}
+
+#[test]
+fn bbox_has_xyxy_and_xywh() {
+    let value = serde_json::to_value(Bbox::new(10., 20., 30., 40., 1, 0.5)).unwrap();
+    assert_eq!(value["xyxy"], serde_json::json!([10., 20., 40., 60.]));
+    assert_eq!(value["xywh"], serde_json::json!([10., 20., 30., 40.]));
+    assert_eq!(value["id"], 1);
+
+    // either form is enough to read it back
+    let xyxy: Bbox =
+        serde_json::from_str(r#"{"xyxy": [10, 20, 40, 60], "id": 1, "confidence": 0.5}"#).unwrap();
+    assert_eq!(xyxy, Bbox::new(10., 20., 30., 40., 1, 0.5));
+    assert!(serde_json::from_str::<Bbox>(r#"{"id": 1, "confidence": 0.5}"#).is_err());
+}
+
+#[test]
+fn classification_round_trip() {
+    let probs = Embedding::new(Array::from_vec(vec![0.1, 0.7, 0.2]).into_dyn());
+    let y = YOLOResult::new(Some(probs), None, None, None);
+    let record = y.to_record(&names(), MaskEncoding::None);
+    assert_eq!(record.classes[0].name, "nut");
+    assert_eq!(record.classes.len(), 3);
+
+    let back = ResultRecord::from_json(&record.to_json().unwrap()).unwrap();
+    assert_eq!(back.to_result(), y);
+    assert!(serde_json::from_str::<Embedding>(r#"{"shape": [2, 2], "data": [1.0]}"#).is_err());
+}
+
+#[test]
+fn obbs_round_trip() {
+    let y = YOLOResult {
+        obbs: Some(vec![Obb::new(50., 40., 30., 10., 0.3, 1, 0.9)]),
+        ..Default::default()
+    };
+    let record = y.to_record(&names(), MaskEncoding::None);
+    assert_eq!(record.obbs[0].name, "nut");
+    let back = ResultRecord::from_msgpack(&record.to_msgpack().unwrap()).unwrap();
+    assert_eq!(back.to_result(), y);
+}
+
+#[test]
+fn masks_round_trip_as_rle() {
+    let y = segmentation();
+    let record = y.to_record(&names(), MaskEncoding::Rle);
+    assert!(matches!(
+        record.detections[0].mask,
+        Some(MaskRecord::Rle(ref rle)) if rle.area() > 0
+    ));
+
+    // decoded again from the RLE, the masks encode to the same record
+    let back = ResultRecord::from_json(&record.to_json().unwrap()).unwrap();
+    let again = back.to_result().to_record(&names(), MaskEncoding::Rle);
+    assert_eq!(again, record);
+    assert_eq!(
+        back.to_result().masks_rle(),
+        y.masks_rle(),
+        "same masks as the decoded protos"
+    );
+}
+
+#[test]
+fn masks_as_polygons_and_none() {
+    let y = segmentation();
+    let polygons = y.to_record(&names(), MaskEncoding::Polygons);
+    assert!(matches!(
+        polygons.detections[0].mask,
+        Some(MaskRecord::Polygons(ref p)) if !p.is_empty()
+    ));
+    let back = ResultRecord::from_msgpack(&polygons.to_msgpack().unwrap()).unwrap();
+    assert_eq!(back, polygons);
+    assert!(back.to_result().masks().is_none());
+
+    let none = y.to_record(&names(), MaskEncoding::None);
+    assert!(none.detections.iter().all(|d| d.mask.is_none()));
+}
+
+#[test]
+fn newer_versions_are_refused() {
+    let mut record = detections().to_record(&names(), MaskEncoding::None);
+    record.version += 1;
+    assert!(ResultRecord::from_json(&record.to_json().unwrap()).is_err());
+    assert!(ResultRecord::from_msgpack(&record.to_msgpack().unwrap()).is_err());
+
+    // fields of later versions may be missing from older records
+    let old = r#"{"version": 1, "model_id": null, "probs": null}"#;
+    assert_eq!(
+        ResultRecord::from_json(old).unwrap().to_result(),
+        YOLOResult::default()
+    );
+}
@@ -0,0 +1,63 @@
+use app::model::frame_bbox;
+
+// a 640x640 input and a 1280x720 frame, letterboxed at ratio 0.5
+const INPUT: f32 = 640.;
+const WIDTH: f32 = 1280.;
+const HEIGHT: f32 = 720.;
+const RATIO: f32 = 0.5;
+
+fn to_input(xywh: [f32; 4], scale: f32, flip: bool) -> [f32; 4] {
+    // what the model sees of a frame box: letterboxed at RATIO * scale, then mirrored
+    let r = RATIO * scale;
+    let [x, y, w, h] = xywh;
+    let cx = (x + w / 2.) * r;
+    let cx = if flip { INPUT - cx } else { cx };
+    [cx, (y + h / 2.) * r, w * r, h * r]
+}
+
+fn assert_close(a: [f32; 4], b: [f32; 4]) {
+    for (a, b) in a.iter().zip(b) {
+        assert!((a - b).abs() < 1e-3, "{a:?} != {b:?}");
+    }
+}
+
+#[test]
+fn augmented_boxes_map_back_to_the_frame() {
+    let frame = [100., 200., 300., 150.];
+    for scale in [1., 0.83, 0.67] {
+        for flip in [false, true] {
+            let bbox = to_input(frame, scale, flip);
+            assert_close(
+                frame_bbox(bbox, RATIO * scale, flip, INPUT, WIDTH, HEIGHT),
+                frame,
+            );
+        }
+    }
+}
+
+#[test]
+fn flip_mirrors_around_the_input_center() {
+    // the left edge of the input is the right edge of the letterboxed frame when flipped
+    assert_close(
+        frame_bbox([0., 100., 0., 0.], RATIO, true, INPUT, WIDTH, HEIGHT),
+        [WIDTH, 200., 0., 0.],
+    );
+    assert_close(
+        frame_bbox(
+            [INPUT / 2., 100., 40., 20.],
+            RATIO,
+            true,
+            INPUT,
+            WIDTH,
+            HEIGHT,
+        ),
+        [WIDTH / 2. - 40., 180., 80., 40.],
+    );
+}
+
+#[test]
+fn corners_are_clamped_to_the_frame() {
+    let [x, y, w, h] = frame_bbox([5., 5., 40., 40.], RATIO, false, INPUT, WIDTH, HEIGHT);
+    assert_eq!([x, y], [0., 0.]);
+    assert_eq!([w, h], [80., 80.]);
+}